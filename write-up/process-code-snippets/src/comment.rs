@@ -0,0 +1,2014 @@
+// lintrans - The linear transformation visualizer
+// Copyright (C) 2021-2022 D. Dyson (DoctorDalek1963)
+
+// This program is licensed under GNU GPLv3, available here:
+// <https://www.gnu.org/licenses/gpl-3.0.html>
+
+//! This module provides the [`Comment`] struct, which represents a single snippet comment in a
+//! LaTeX file, and the logic to resolve it into a [`Text`] using the lintrans git history.
+
+use color_eyre::{eyre::eyre, Result};
+use git2::{Oid, Repository};
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{char, u64 as nom_u64},
+    combinator::map,
+    multi::separated_list1,
+    sequence::{preceded, separated_pair, terminated},
+    IResult,
+};
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Once, OnceLock},
+};
+
+use crate::{
+    config::{Caption, Config},
+    text::{Body, Text},
+};
+
+lazy_static! {
+    /// The pattern that identifies a snippet comment in a LaTeX file.
+    ///
+    /// A snippet comment is a two-line ``%:`` block: the first line gives the hash of the
+    /// commit to take the file from (abbreviated to no less than eight characters, if desired), and the second gives the filename (relative to the repo
+    /// root), optionally followed by a colon and comma-separated line ranges, and then by config
+    /// options. The options can be given in any order, although [`Config::details`] always emits
+    /// them alphabetically. For example:
+    ///
+    /// ```latex
+    /// %: ac46027a9bc9adc02f379f11bb1351b18d4f5138
+    /// %: src/lintrans/matrices/wrapper.py:45-56 highlight=47-48 noscopes
+    /// ```
+    ///
+    /// Richly-configured snippets can put a TOML table on an optional third line instead of
+    /// crowding the options onto the filename line:
+    ///
+    /// ```latex
+    /// %: ac46027a9bc9adc02f379f11bb1351b18d4f5138
+    /// %: src/lintrans/matrices/wrapper.py:45-56
+    /// %: { language = "rust", highlight = "47-48", noscopes = true }
+    /// ```
+    ///
+    /// While drafting, the special hash ``WORKTREE`` reads the file from the working tree
+    /// instead of a commit, at the cost of reproducibility.
+    ///
+    /// For continuously-rebuilt documents, the placeholder ``HEAD`` pins at build time
+    /// instead: it is substituted with the commit ``HEAD`` resolved to when the tool
+    /// started, so the emitted info comment records the exact commit that was used.
+    ///
+    /// A tag name like ``v0.3.0`` can stand in for the hash, which keeps the provenance
+    /// human-meaningful in teaching materials; the info comment then shows the tag with the
+    /// resolved commit alongside it.
+    ///
+    /// A comment can also be a single ``%: @name`` line referring to an entry in the manifest
+    /// file loaded with [`load_manifest`].
+    pub static ref COMMENT_PATTERN: Regex = Regex::new(concat!(
+        r"(?m)^(?:%: @(?P<name>\w+)|",
+        r"%: (?P<hash>[0-9a-f]{8,40}|WORKTREE|HEAD|v[0-9][0-9A-Za-z.\-]*)\n",
+        r"%: (?P<filename>[^\s:]+)(?::(?P<line_ranges>[0-9,\-$]*))?(?P<options>[^\n]*)",
+        r"(?:\n%: (?P<inline_config>\{[^\n]*\}))?)$"
+    ))
+    .unwrap();
+
+    /// The default pattern matching the copyright comment at the top of every lintrans source
+    /// file, used when no pattern is configured with [`config::set_copyright_pattern`].
+    ///
+    /// The whole match is stripped from whole-file snippets, however many lines it spans. The
+    /// blank line separating the comment from the code is skipped separately, so the pattern
+    /// doesn't need to consume it.
+    static ref COPYRIGHT_COMMENT_PATTERN: Regex = Regex::new(concat!(
+        r"^(#!/usr/bin/env python\n\n)?",
+        r"# lintrans - The linear transformation visualizer\n",
+        r"# Copyright \(C\) (20\d\d-)?20\d\d D\. Dyson \(DoctorDalek1963\)\n",
+        r"\n",
+        r"# This program is licensed under GNU GPLv3, available here:\n",
+        r"# <https://www\.gnu\.org/licenses/gpl-3\.0\.html>\n"
+    ))
+    .unwrap();
+}
+
+/// The named snippet definitions loaded from a manifest file, if any.
+static MANIFEST: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// The hash that ``HEAD`` placeholders are substituted with, resolved once at startup.
+static HEAD_HASH: OnceLock<String> = OnceLock::new();
+
+/// The guard making sure the ``HEAD`` substitution is only announced once per run.
+static HEAD_WARNING: Once = Once::new();
+
+/// Set the hash that ``HEAD`` placeholders resolve to.
+///
+/// Setting twice has no effect, so this should be called once, before any comments are parsed.
+pub fn set_head_hash(hash: &str) {
+    let _ = HEAD_HASH.set(hash.to_string());
+}
+
+/// Load named snippet definitions from the text of a manifest file like ``snippets.list``.
+///
+/// Each entry is a line like ``@name`` followed by the body of a normal snippet comment without
+/// the ``%: `` prefixes, ending at the next blank line:
+///
+/// ```text
+/// @parser_core
+/// ac46027a9bc9adc02f379f11bb1351b18d4f5138
+/// src/lintrans/matrices/wrapper.py:45-56 noscopes
+/// ```
+///
+/// Loading twice has no effect, so this should be called once, before any comments are parsed.
+pub fn load_manifest(text: &str) -> Result<()> {
+    let mut manifest = HashMap::new();
+    let mut lines = text.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some(name) = line.strip_prefix('@') else {
+            return Err(eyre!(
+                "Expected an @name line in the manifest, found {line:?}"
+            ));
+        };
+
+        let mut body_lines: Vec<String> = vec![];
+        while let Some(next) = lines.peek() {
+            if next.trim().is_empty() {
+                break;
+            }
+            body_lines.push(format!("%: {}", lines.next().unwrap().trim()));
+        }
+        if body_lines.is_empty() {
+            return Err(eyre!("Manifest entry @{name} has no body"));
+        }
+
+        manifest.insert(name.to_string(), body_lines.join("\n"));
+    }
+
+    let _ = MANIFEST.set(manifest);
+    Ok(())
+}
+
+/// A single line range from a snippet comment, before being resolved against the file.
+///
+/// End-anchored ranges can only be resolved once the length of the file is known, so the ranges
+/// are stored as written and resolved lazily in [`Comment::get_text`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineRange {
+    /// An explicit inclusive range like ``45-56`` or a single line like ``45``.
+    Absolute(usize, usize),
+
+    /// A range like ``200-$``, from a given line to the end of the file.
+    ToEnd(usize),
+
+    /// A range like ``-10``, meaning the last n lines of the file.
+    LastN(usize),
+}
+
+impl LineRange {
+    /// Resolve this range into explicit first and last line numbers for a file with the given
+    /// number of lines.
+    pub fn resolve(self, line_count: usize) -> (usize, usize) {
+        match self {
+            Self::Absolute(first, last) => (first, last),
+            Self::ToEnd(first) => (first, line_count),
+            Self::LastN(n) => (line_count.saturating_sub(n) + 1, line_count),
+        }
+    }
+}
+
+/// Parse a single line range like ``45-56``, ``45``, ``200-$``, or ``-10``.
+fn line_range(input: &str) -> IResult<&str, LineRange> {
+    alt((
+        map(
+            separated_pair(nom_u64, char('-'), nom_u64),
+            |(first, last)| LineRange::Absolute(first as usize, last as usize),
+        ),
+        map(terminated(nom_u64, tag("-$")), |first| {
+            LineRange::ToEnd(first as usize)
+        }),
+        map(preceded(char('-'), nom_u64), |n| {
+            LineRange::LastN(n as usize)
+        }),
+        map(nom_u64, |line| {
+            LineRange::Absolute(line as usize, line as usize)
+        }),
+    ))(input)
+}
+
+/// Parse a comma-separated list of line ranges.
+fn parse_line_ranges(input: &str) -> IResult<&str, Vec<LineRange>> {
+    separated_list1(char(','), line_range)(input)
+}
+
+/// The resolved source of a snippet comment: the file contents it refers to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResolvedSource {
+    /// The contents of a single file.
+    File(String),
+
+    /// The path and contents of every file directly inside a directory, in sorted order.
+    Directory(Vec<(PathBuf, String)>),
+}
+
+/// A snippet comment resolved against the repo, ready to be rendered or inspected.
+///
+/// Resolution borrows the [`Comment`], so a comment can be validated (or its commit inspected)
+/// and then reused; [`Comment::get_text`] builds on this.
+#[derive(Debug)]
+pub struct ResolvedSnippet<'repo> {
+    /// The commit that the snippet is pinned to.
+    pub commit: git2::Commit<'repo>,
+
+    /// The resolved contents of the referenced file or directory.
+    pub source: ResolvedSource,
+}
+
+/// A struct to represent a single snippet comment in a LaTeX file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Comment {
+    /// The hash of the commit to take the file from, possibly abbreviated. A ``HEAD``
+    /// placeholder has already been substituted here; see [`Comment::hash_as_written`].
+    pub hash: String,
+
+    /// The hash exactly as written in the source comment. The rewrite modes write this back,
+    /// so a ``HEAD`` placeholder stays a placeholder instead of being pinned in the source.
+    pub hash_as_written: String,
+
+    /// The name of the file to take the snippet from, relative to the repo root.
+    pub filename: PathBuf,
+
+    /// The line ranges of the snippet as written, or `None` for the whole file.
+    pub line_ranges: Option<Vec<LineRange>>,
+
+    /// The configuration options of this snippet.
+    pub config: Config,
+}
+
+/// An error from resolving a snippet against the repo.
+///
+/// [`Comment::get_text`] returns these instead of bare report strings so that programmatic
+/// callers can match on the failure, while `main` still prints them through color_eyre.
+#[derive(Debug)]
+pub enum SnippetError {
+    /// The snippet's commit (or the commit of a ``diff=`` option) isn't in the repo.
+    MissingCommit(String),
+
+    /// The snippet's abbreviated hash matches more than one commit.
+    AmbiguousHash {
+        /// The abbreviated hash as written.
+        prefix: String,
+
+        /// The full hashes of every commit it matches.
+        candidates: Vec<Oid>,
+    },
+
+    /// The snippet's file isn't in its commit's tree. Any files elsewhere in the tree with the
+    /// same basename are listed as rename candidates.
+    MissingFile {
+        /// The path of the missing file.
+        path: PathBuf,
+
+        /// The hash of the commit that was searched.
+        hash: String,
+
+        /// Files elsewhere in the tree with the same basename, if any.
+        candidates: Vec<PathBuf>,
+    },
+
+    /// The snippet's path points at something that isn't a blob.
+    NotBlob {
+        /// The path of the entry.
+        path: PathBuf,
+
+        /// The hash of the commit it was read from.
+        hash: String,
+    },
+
+    /// The snippet's file isn't valid UTF-8.
+    NotUtf8(PathBuf),
+
+    /// A resolved line range falls outside the file.
+    RangeOutOfBounds {
+        /// The first line of the range.
+        first: usize,
+
+        /// The last line of the range.
+        last: usize,
+
+        /// The path of the file the range was applied to.
+        path: PathBuf,
+    },
+
+    /// An underlying git operation failed.
+    Git(git2::Error),
+
+    /// Any other failure, described as a message.
+    Other(String),
+}
+
+impl fmt::Display for SnippetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingCommit(hash) => {
+                write!(f, "Couldn't find commit {hash} in the repo")
+            }
+            Self::AmbiguousHash { prefix, candidates } => write!(
+                f,
+                "Short hash {prefix} is ambiguous; it could be {}",
+                candidates.iter().join(" or ")
+            ),
+            Self::MissingFile {
+                path,
+                hash,
+                candidates,
+            } if candidates.is_empty() => {
+                write!(f, "Couldn't find {} at {}", path.display(), short_hash(hash))
+            }
+            Self::MissingFile {
+                path,
+                hash,
+                candidates,
+            } => write!(
+                f,
+                "Couldn't find {} at {}; did you mean {}?",
+                path.display(),
+                short_hash(hash),
+                candidates.iter().map(|path| path.display()).join(" or ")
+            ),
+            Self::NotBlob { path, hash } => {
+                write!(f, "{} at {} is not a file", path.display(), short_hash(hash))
+            }
+            Self::NotUtf8(path) => write!(f, "{} is not valid UTF-8", path.display()),
+            Self::RangeOutOfBounds { first, last, path } => {
+                write!(
+                    f,
+                    "Line range {first}-{last} is invalid for {}",
+                    path.display()
+                )
+            }
+            Self::Git(error) => write!(f, "{error}"),
+            Self::Other(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for SnippetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Git(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<git2::Error> for SnippetError {
+    fn from(error: git2::Error) -> Self {
+        Self::Git(error)
+    }
+}
+
+impl Comment {
+    /// Parse a [`Comment`] from the text of a LaTeX comment matching [`struct@COMMENT_PATTERN`].
+    pub fn from_latex_comment(text: &str) -> Option<Self> {
+        let captures = COMMENT_PATTERN.captures(text)?;
+
+        // An @name reference expands to its manifest entry, which is a normal comment body
+        if let Some(name) = captures.name("name") {
+            return Self::from_latex_comment(MANIFEST.get()?.get(name.as_str())?);
+        }
+
+        // A stray colon with no ranges after it means the whole file, same as no colon at all.
+        // Ranges that don't parse, or don't parse completely (like ``5-``), make the whole
+        // comment unparseable instead of panicking or silently truncating
+        let line_ranges = match captures
+            .name("line_ranges")
+            .filter(|m| !m.as_str().trim().is_empty())
+        {
+            Some(m) => match parse_line_ranges(m.as_str()) {
+                Ok(("", ranges)) => Some(ranges),
+                _ => return None,
+            },
+            None => None,
+        };
+        let mut config = Config::parse(captures.name("options").map_or("", |m| m.as_str())).ok()?;
+        if let Some(inline_config) = captures.name("inline_config") {
+            config.apply_inline(inline_config.as_str()).ok()?;
+        }
+
+        // A HEAD placeholder becomes the hash resolved at startup, so both the processing
+        // and the emitted info comment record the concrete commit that was used; the raw
+        // capture is kept alongside for the modes that rewrite the source
+        let hash_as_written = captures["hash"].to_string();
+        let hash = match &captures["hash"] {
+            "HEAD" => match HEAD_HASH.get() {
+                Some(hash) => {
+                    HEAD_WARNING.call_once(|| {
+                        crate::warnings::warn(&format!("resolved HEAD to {hash}"));
+                    });
+                    hash.clone()
+                }
+                None => String::from("HEAD"),
+            },
+            hash => hash.to_string(),
+        };
+
+        Some(Self {
+            hash,
+            hash_as_written,
+            filename: PathBuf::from(&captures["filename"]),
+            line_ranges,
+            config,
+        })
+    }
+
+    /// Return a human-readable summary of this comment.
+    pub fn details(&self) -> String {
+        let line_ranges = match &self.line_ranges {
+            Some(ranges) => format_line_ranges(ranges),
+            None => String::from("all"),
+        };
+
+        format!(
+            "{} {}:{} ({})",
+            short_hash(&self.hash),
+            self.filename.display(),
+            line_ranges,
+            self.config.details()
+        )
+    }
+
+    /// Return this comment in its canonical two-line ``%:`` form.
+    ///
+    /// The hash comes out exactly as pinned (``--canonicalize`` expands abbreviations before
+    /// serializing, where the repo is at hand), the ranges keep the comment syntax, and the
+    /// options come out in [`Config::details`]'s alphabetical order. Re-parsing the result
+    /// yields an equal comment for everything the flat syntax can express; a value that
+    /// can't survive it (a space-containing language from an inline TOML table, say) doesn't
+    /// round-trip, which ``--canonicalize`` checks for before writing anything back.
+    pub fn to_comment_string(&self) -> String {
+        let mut line = format!("%: {}", self.filename.display());
+        if let Some(ranges) = &self.line_ranges {
+            line.push(':');
+            line.push_str(&format_line_ranges(ranges));
+        }
+        let options = self.config.details();
+        if !options.is_empty() {
+            line.push(' ');
+            line.push_str(&options);
+        }
+        format!("%: {}\n{line}", self.hash_as_written)
+    }
+
+    /// Resolve this comment's hash, which may be abbreviated, into a full commit id.
+    ///
+    /// An ambiguous prefix lists every commit it matches, turning git's dead-end "ambiguous
+    /// prefix" error into something the author can act on directly.
+    pub fn resolve_oid(&self, repo: &Repository) -> Result<Oid, SnippetError> {
+        // A tag name resolves through the ref machinery rather than the object database; an
+        // annotated tag peels down to the commit it points at
+        if self.hash != "WORKTREE" && !self.hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return repo
+                .find_reference(&format!("refs/tags/{}", self.hash))
+                .and_then(|reference| reference.peel_to_commit())
+                .map(|commit| commit.id())
+                .map_err(|_| SnippetError::MissingCommit(self.hash.clone()));
+        }
+
+        if self.hash.len() == 40 {
+            return Oid::from_str(&self.hash)
+                .map_err(|_| SnippetError::MissingCommit(self.hash.clone()));
+        }
+
+        let mut candidates: Vec<Oid> = vec![];
+        repo.odb()?.foreach(|oid| {
+            if oid.to_string().starts_with(&self.hash) && repo.find_commit(*oid).is_ok() {
+                candidates.push(*oid);
+            }
+            true
+        })?;
+
+        match candidates.as_slice() {
+            [oid] => Ok(*oid),
+            [] => Err(SnippetError::MissingCommit(self.hash.clone())),
+            _ => Err(SnippetError::AmbiguousHash {
+                prefix: self.hash.clone(),
+                candidates,
+            }),
+        }
+    }
+
+    /// Resolve this comment against the repo, finding its commit and reading its file (or
+    /// directory) contents, without rendering anything.
+    pub fn resolve<'repo>(
+        &self,
+        repo: &'repo Repository,
+    ) -> Result<ResolvedSnippet<'repo>, SnippetError> {
+        // A WORKTREE snippet reads the file from disk while drafting, so its output changes
+        // with the working tree and can't be reproduced later; the commit is HEAD purely so
+        // that blame and caption=commit have something sensible to point at
+        if self.hash == "WORKTREE" {
+            crate::warnings::warn(&format!(
+                "{} is read from the working tree, which is not reproducible; pin a commit \
+                 hash before publishing",
+                self.filename.display()
+            ));
+
+            let commit = repo.head()?.peel_to_commit()?;
+            let workdir = repo.workdir().ok_or_else(|| {
+                SnippetError::Other(String::from(
+                    "WORKTREE snippets can't be used with a bare repo",
+                ))
+            })?;
+            let content =
+                std::fs::read_to_string(workdir.join(&self.filename)).map_err(|error| {
+                    SnippetError::Other(format!(
+                        "Couldn't read {} from the working tree: {error}",
+                        self.filename.display()
+                    ))
+                })?;
+            return Ok(ResolvedSnippet {
+                commit,
+                source: ResolvedSource::File(content),
+            });
+        }
+
+        let oid = self.resolve_oid(repo)?;
+        let commit = repo
+            .find_commit(oid)
+            .map_err(|_| SnippetError::MissingCommit(self.hash.clone()))?;
+        let tree = commit.tree()?;
+        let entry = match tree.get_path(&self.filename) {
+            Ok(entry) => entry,
+            Err(error) => {
+                // The file may have been renamed since the comment was written, so look for
+                // other files in the tree with the same basename before giving up
+                let candidates = find_files_with_basename(&tree, &self.filename);
+
+                if crate::config::follow_renames() && candidates.len() == 1 {
+                    crate::warnings::warn(&format!(
+                        "resolved {} to {} at {}",
+                        self.filename.display(),
+                        candidates[0].display(),
+                        short_hash(&self.hash)
+                    ));
+                    tree.get_path(&candidates[0])?
+                } else {
+                    drop(error);
+                    return Err(SnippetError::MissingFile {
+                        path: self.filename.clone(),
+                        hash: self.hash.clone(),
+                        candidates,
+                    });
+                }
+            }
+        };
+
+        let source = if entry.kind() == Some(git2::ObjectType::Tree) {
+            let dir_tree = entry
+                .to_object(repo)?
+                .into_tree()
+                .map_err(|_| SnippetError::Other(String::from("Couldn't convert object to tree")))?;
+
+            let mut files = vec![];
+            for child in dir_tree.iter() {
+                if child.kind() != Some(git2::ObjectType::Blob) {
+                    continue;
+                }
+                let Some(name) = child.name() else {
+                    continue;
+                };
+
+                let path = self.filename.join(name);
+                let blob = child.to_object(repo)?.into_blob().map_err(|_| {
+                    SnippetError::NotBlob {
+                        path: path.clone(),
+                        hash: self.hash.clone(),
+                    }
+                })?;
+                let content = decode_blob(blob.content(), &path)?;
+                files.push((path, content));
+            }
+            ResolvedSource::Directory(files)
+        } else {
+            let blob =
+                entry
+                    .to_object(repo)?
+                    .into_blob()
+                    .map_err(|_| SnippetError::NotBlob {
+                        path: self.filename.clone(),
+                        hash: self.hash.clone(),
+                    })?;
+            ResolvedSource::File(decode_blob(blob.content(), &self.filename)?)
+        };
+
+        Ok(ResolvedSnippet { commit, source })
+    }
+
+    /// Resolve this comment into a [`Text`] by reading the file from the lintrans git history.
+    pub fn get_text(self, repo: &Repository) -> Result<Text, SnippetError> {
+        let ResolvedSnippet { commit, source } = self.resolve(repo)?;
+        let oid = commit.id();
+
+        // The caption is resolved here, where the commit is at hand, so the directory and diff
+        // variants below get it for free
+        let caption = match &self.config.caption {
+            Some(Caption::Commit) => match commit.summary() {
+                Some(summary) => Some(summary.to_string()),
+                None => {
+                    crate::warnings::warn(&format!(
+                        "commit {} has no summary to caption with",
+                        short_hash(&self.hash)
+                    ));
+                    None
+                }
+            },
+            Some(Caption::Literal(caption)) => Some(caption.clone()),
+            None => None,
+        };
+
+        let content = match source {
+            ResolvedSource::Directory(files) => {
+                let mut text = self.get_directory_text(files)?;
+                text.oid = oid;
+                text.caption = caption;
+                return Ok(text);
+            }
+            ResolvedSource::File(content) => content,
+        };
+
+        if let Some(diff_hash) = self.config.diff_hash.clone() {
+            let mut text = self.get_diff_text(repo, &content, &diff_hash)?;
+            text.oid = oid;
+            text.caption = caption;
+            return Ok(text);
+        }
+
+        // str::lines strips the \r of CRLF endings, so a Windows-authored file would be
+        // silently normalized when the body is rejoined with plain newlines. Unless the user
+        // asked for that with --normalize-eol, the \r is restored onto the body lines below
+        let crlf = content.contains("\r\n") && !crate::config::normalize_eol();
+        if crlf {
+            crate::warnings::warn(&format!(
+                "{} has CRLF line endings, which will be preserved; pass --normalize-eol to \
+                 convert them",
+                self.filename.display()
+            ));
+        }
+
+        let lines: Vec<&str> = content.lines().collect();
+
+        // A marker= option selects the lines between the SNIPPET-START/END markers in the file
+        // itself, which follows the code through refactors better than fixed line numbers
+        let marker_ranges = match &self.config.marker {
+            Some(name) => Some(vec![find_marker_range(
+                &lines,
+                name,
+                &self.filename,
+                self.config.show_markers,
+            )?]),
+            None => None,
+        };
+
+        let line_ranges: Vec<(usize, usize)> = match marker_ranges.as_ref().or(self.line_ranges.as_ref()) {
+            Some(ranges) => {
+                let mut resolved: Vec<(usize, usize)> = ranges
+                    .iter()
+                    .map(|range| {
+                        let (mut first, mut last) = range.resolve(lines.len());
+
+                        // Scope expansion comes first, so context then grows around the block
+                        if self.config.expand_to_scope {
+                            (first, last) = expand_to_scope(&lines, first, last);
+                        }
+
+                        // Expand each range by the requested context, clamped to the file bounds
+                        (
+                            first.saturating_sub(self.config.context).max(1),
+                            (last + self.config.context).min(lines.len()),
+                        )
+                    })
+                    .collect();
+
+                // Overlapping ranges would render duplicated lines in separate bodies, so sort
+                // the ranges and coalesce any that overlap or touch
+                resolved.sort_unstable();
+                let mut merged: Vec<(usize, usize)> = vec![];
+                for (first, last) in resolved {
+                    match merged.last_mut() {
+                        Some(previous) if first <= previous.1 + 1 => {
+                            if first <= previous.1 {
+                                crate::warnings::warn(&format!(
+                                    "ranges {}-{} and {first}-{last} of {} overlap; merging them",
+                                    previous.0,
+                                    previous.1,
+                                    self.filename.display()
+                                ));
+                            }
+                            previous.1 = previous.1.max(last);
+                        }
+                        _ => merged.push((first, last)),
+                    }
+                }
+                merged
+            }
+            None => {
+                // A whole-file snippet gets its copyright comment stripped. We go by the span
+                // of the match rather than a fixed line count, so headers of any length work
+                let pattern =
+                    crate::config::copyright_pattern().unwrap_or(&COPYRIGHT_COMMENT_PATTERN);
+                let mut first = match pattern.find(&content) {
+                    Some(m) if m.start() == 0 && !self.config.keep_copyright_comment => {
+                        content[..m.end()].lines().count() + 1
+                    }
+                    _ => 1,
+                };
+
+                // The blank line separating the comment from the code is skipped too,
+                // unless the user asked to keep it
+                if first > 1
+                    && !self.config.keep_copyright_blank
+                    && lines
+                        .get(first - 1)
+                        .is_some_and(|line| line.trim().is_empty())
+                {
+                    first += 1;
+                }
+
+                // A stripped header can take a shebang line with it; keep_shebang brings
+                // that line back as its own range, with the usual gap down to the code
+                if self.config.keep_shebang
+                    && first > 1
+                    && lines.first().is_some_and(|line| line.starts_with("#!"))
+                {
+                    vec![(1, 1), (first, lines.len())]
+                } else {
+                    vec![(first, lines.len())]
+                }
+            }
+        };
+
+        // elide=a-b splits each range around an elided interior, rendering as the usual
+        // multi-body form with a ... gap
+        let line_ranges = if let Some(elide) = &self.config.elide {
+            let (efirst, elast) = match parse_line_ranges(elide) {
+                Ok(("", ranges)) if ranges.len() == 1 => match ranges[0] {
+                    LineRange::Absolute(first, last) if first <= last => (first, last),
+                    _ => {
+                        return Err(SnippetError::Other(format!(
+                            "elide range {elide:?} must be an explicit first-last range"
+                        )))
+                    }
+                },
+                _ => {
+                    return Err(SnippetError::Other(format!(
+                        "Failed to parse elide range {elide:?}"
+                    )))
+                }
+            };
+
+            let mut split: Vec<(usize, usize)> = vec![];
+            let mut elided = false;
+            for (first, last) in line_ranges {
+                if first < efirst && elast < last {
+                    split.push((first, efirst - 1));
+                    split.push((elast + 1, last));
+                    elided = true;
+                } else {
+                    split.push((first, last));
+                }
+            }
+            if !elided {
+                crate::warnings::warn(&format!(
+                    "elide range {elide} falls strictly inside no displayed range of {}",
+                    self.filename.display()
+                ));
+            }
+            split
+        } else {
+            line_ranges
+        };
+
+        // exclude=15,22 drops single noisy lines from inside the displayed ranges, leaning
+        // on the same multi-body rendering; the gap each one leaves shows an ellipsis even
+        // though only one line was skipped
+        let line_ranges = if let Some(exclude) = &self.config.exclude {
+            let mut excluded: Vec<usize> = vec![];
+            for part in exclude.split(',') {
+                excluded.push(part.parse().map_err(|_| {
+                    SnippetError::Other(format!("Failed to parse excluded line {part:?}"))
+                })?);
+            }
+            excluded.sort_unstable();
+
+            let mut split: Vec<(usize, usize)> = vec![];
+            let mut dropped = false;
+            for (first, last) in line_ranges {
+                let mut start = first;
+                for &line in &excluded {
+                    if start <= line && line <= last {
+                        if line > start {
+                            split.push((start, line - 1));
+                        }
+                        start = line + 1;
+                        dropped = true;
+                    }
+                }
+                if start <= last {
+                    split.push((start, last));
+                }
+            }
+            if !dropped {
+                crate::warnings::warn(&format!(
+                    "exclude={exclude} removed no lines from {}",
+                    self.filename.display()
+                ));
+            }
+            if split.is_empty() {
+                return Err(SnippetError::Other(format!(
+                    "exclude={exclude} removes every line of {} at {}",
+                    self.filename.display(),
+                    short_hash(&self.hash)
+                )));
+            }
+            split
+        } else {
+            line_ranges
+        };
+
+        for &(first, last) in &line_ranges {
+            if first < 1 || last > lines.len() || first > last {
+                return Err(SnippetError::RangeOutOfBounds {
+                    first,
+                    last,
+                    path: self.filename.clone(),
+                });
+            }
+        }
+
+        let mut bodies: Vec<Body> = line_ranges
+            .iter()
+            .map(|&(first, last)| Body {
+                first,
+                last,
+                lines: lines[(first - 1)..last]
+                    .iter()
+                    .map(|line| line.to_string())
+                    .collect(),
+            })
+            .collect();
+
+        if self.config.trim_blank_body_edges {
+            // Trim blank lines from the edges of each body, bumping the line numbers so they
+            // stay accurate to the first and last non-blank content
+            for body in &mut bodies {
+                while body
+                    .lines
+                    .first()
+                    .is_some_and(|line| line.trim().is_empty())
+                {
+                    body.lines.remove(0);
+                    body.first += 1;
+                }
+                while body.lines.last().is_some_and(|line| line.trim().is_empty()) {
+                    body.lines.pop();
+                    body.last -= 1;
+                }
+            }
+            bodies.retain(|body| !body.lines.is_empty());
+
+            if bodies.is_empty() {
+                return Err(SnippetError::Other(format!(
+                    "Every line of {} at {} is blank after trimming",
+                    self.filename.display(),
+                    short_hash(&self.hash)
+                )));
+            }
+        }
+
+        let mut scopes = if self.config.noscopes {
+            vec![]
+        } else {
+            find_scopes(&lines, bodies[0].first)
+        };
+
+        // A hand-written header replaces the detected scopes entirely. It's looked up in the
+        // file so that it can show its real line number when it does exist there
+        if let Some(header) = &self.config.scope_header {
+            let number = lines
+                .iter()
+                .position(|line| line.trim() == header.trim())
+                .map_or(0, |index| index + 1);
+            scopes = vec![(number, header.clone())];
+        }
+
+        if self.config.dedent {
+            // Strip the minimum common indentation of the body lines, and dedent the scope
+            // lines by the same amount to stay visually consistent
+            let indent = bodies
+                .iter()
+                .flat_map(|body| &body.lines)
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| line.chars().take_while(|&c| c == ' ').count())
+                .min()
+                .unwrap_or(0);
+
+            if indent > 0 {
+                for line in bodies
+                    .iter_mut()
+                    .flat_map(|body| &mut body.lines)
+                    .chain(scopes.iter_mut().map(|(_, line)| line))
+                {
+                    let leading = line.chars().take_while(|&c| c == ' ').count();
+                    *line = line[indent.min(leading)..].to_string();
+                }
+            }
+        }
+
+        if crlf {
+            for line in bodies.iter_mut().flat_map(|body| &mut body.lines) {
+                line.push('\r');
+            }
+        }
+
+        let mut config = self.config;
+        if config.language.is_none() {
+            config.language = Some(infer_language(&self.filename));
+        }
+
+        if let Some(relative) = config.highlight_lines_relative.take() {
+            // Translate ranges counted within the displayed body into the absolute file line
+            // numbers that minted expects, and merge them into the highlighted lines
+            let numbers: Vec<usize> = bodies
+                .iter()
+                .flat_map(|body| body.first..=body.last)
+                .collect();
+            let (rest, ranges) = parse_line_ranges(&relative).map_err(|e| {
+                SnippetError::Other(format!(
+                    "Failed to parse relative highlight ranges {relative:?}: {e}"
+                ))
+            })?;
+            if !rest.is_empty() {
+                return Err(SnippetError::Other(format!(
+                    "Trailing text {rest:?} in relative highlight ranges {relative:?}"
+                )));
+            }
+
+            let absolute = ranges
+                .iter()
+                .map(|range| {
+                    let (first, last) = range.resolve(numbers.len());
+                    if first < 1 || last > numbers.len() || first > last {
+                        return Err(SnippetError::Other(format!(
+                            "Relative highlight range {first}-{last} is outside the snippet"
+                        )));
+                    }
+                    Ok(if first == last {
+                        numbers[first - 1].to_string()
+                    } else {
+                        format!("{}-{}", numbers[first - 1], numbers[last - 1])
+                    })
+                })
+                .collect::<Result<Vec<String>, SnippetError>>()?
+                .join(",");
+
+            config.highlight_lines = Some(match config.highlight_lines {
+                Some(existing) => format!("{existing},{absolute}"),
+                None => absolute,
+            });
+        }
+
+        if let Some(old_hash) = config.highlight_diff.take() {
+            // Highlight the displayed lines that differ from the file at the older commit,
+            // merging with any manual highlights
+            let old_oid = Oid::from_str(&old_hash)
+                .map_err(|_| SnippetError::MissingCommit(old_hash.clone()))?;
+            let old_commit = repo
+                .find_commit(old_oid)
+                .map_err(|_| SnippetError::MissingCommit(old_hash.clone()))?;
+            let old_blob = old_commit
+                .tree()?
+                .get_path(&self.filename)
+                .map_err(|_| SnippetError::MissingFile {
+                    path: self.filename.clone(),
+                    hash: old_hash.clone(),
+                    candidates: vec![],
+                })?
+                .to_object(repo)?
+                .into_blob()
+                .map_err(|_| SnippetError::NotBlob {
+                    path: self.filename.clone(),
+                    hash: old_hash.clone(),
+                })?;
+
+            let patch = git2::Patch::from_buffers(
+                old_blob.content(),
+                Some(&self.filename),
+                content.as_bytes(),
+                Some(&self.filename),
+                None,
+            )?;
+
+            let mut numbers: Vec<usize> = vec![];
+            for hunk_index in 0..patch.num_hunks() {
+                let (_, line_count) = patch.hunk(hunk_index)?;
+                for line_index in 0..line_count {
+                    let line = patch.line_in_hunk(hunk_index, line_index)?;
+                    if line.origin() != '+' {
+                        continue;
+                    }
+                    let Some(number) = line.new_lineno() else {
+                        continue;
+                    };
+                    let number = number as usize;
+                    if bodies
+                        .iter()
+                        .any(|body| (body.first..=body.last).contains(&number))
+                    {
+                        numbers.push(number);
+                    }
+                }
+            }
+
+            if !numbers.is_empty() {
+                let joined = numbers.iter().join(",");
+                config.highlight_lines = Some(match config.highlight_lines {
+                    Some(existing) => format!("{existing},{joined}"),
+                    None => joined,
+                });
+            }
+        }
+
+        if let Some(pattern) = config.highlight_regex.take() {
+            // Highlight every body line matching the regex, merging with any manual highlights
+            let regex = Regex::new(&pattern)
+                .map_err(|e| SnippetError::Other(format!("Invalid highlight_regex {pattern:?}: {e}")))?;
+
+            let numbers: Vec<usize> = bodies
+                .iter()
+                .flat_map(|body| {
+                    body.lines
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, line)| regex.is_match(line))
+                        .map(|(i, _)| body.first + i)
+                        .collect::<Vec<usize>>()
+                })
+                .collect();
+
+            if !numbers.is_empty() {
+                let joined = numbers.iter().join(",");
+                config.highlight_lines = Some(match config.highlight_lines {
+                    Some(existing) => format!("{existing},{joined}"),
+                    None => joined,
+                });
+            }
+        }
+
+        let blame_info = if config.blame {
+            // The minimal useful form of blame: the distinct commits that last touched the
+            // included lines, in the order they first appear
+            let mut blame_options = git2::BlameOptions::new();
+            blame_options.newest_commit(commit.id());
+            let blame = repo.blame_file(&self.filename, Some(&mut blame_options))?;
+
+            let mut hashes: Vec<String> = vec![];
+            for &(first, last) in &line_ranges {
+                for line in first..=last {
+                    if let Some(hunk) = blame.get_line(line) {
+                        let hash = hunk.final_commit_id().to_string()[..8].to_string();
+                        if !hashes.contains(&hash) {
+                            hashes.push(hash);
+                        }
+                    }
+                }
+            }
+            Some(format!("touched by: {}", hashes.join(", ")))
+        } else {
+            None
+        };
+
+        // A max-lines limit catches fat-fingered whole-file includes before PDF generation;
+        // allow_long marks the snippets that really are meant to be that big
+        if let Some(max_lines) = crate::config::max_lines() {
+            let total: usize = bodies.iter().map(|body| body.lines.len()).sum();
+            if total > max_lines && !config.allow_long {
+                crate::warnings::warn(&format!(
+                    "{}: snippet body is {total} lines, over the limit of {max_lines}; \
+                     add allow_long if this is intentional",
+                    self.filename.display()
+                ));
+            }
+        }
+
+        Ok(Text {
+            hash: self.hash,
+            oid,
+            filename: self.filename,
+            scopes,
+            blame_info,
+            caption,
+            repo_url: crate::config::repo_url().to_string(),
+            bodies,
+            config,
+        })
+    }
+}
+
+impl Comment {
+    /// Resolve a snippet as a unified diff of the file between another commit and this one.
+    ///
+    /// The diff goes from the other commit to the snippet's own commit, so context and added
+    /// lines come from the newer version. Line ranges are ignored: this is a whole-file diff,
+    /// rendered with minted's ``diff`` lexer unless another language is given explicitly.
+    fn get_diff_text(
+        self,
+        repo: &Repository,
+        new_content: &str,
+        other_hash: &str,
+    ) -> Result<Text, SnippetError> {
+        let old_oid = Oid::from_str(other_hash)
+            .map_err(|_| SnippetError::MissingCommit(other_hash.to_string()))?;
+        let old_commit = repo
+            .find_commit(old_oid)
+            .map_err(|_| SnippetError::MissingCommit(other_hash.to_string()))?;
+        let old_blob = old_commit
+            .tree()?
+            .get_path(&self.filename)
+            .map_err(|_| SnippetError::MissingFile {
+                path: self.filename.clone(),
+                hash: other_hash.to_string(),
+                candidates: vec![],
+            })?
+            .to_object(repo)?
+            .into_blob()
+            .map_err(|_| SnippetError::NotBlob {
+                path: self.filename.clone(),
+                hash: other_hash.to_string(),
+            })?;
+
+        let patch = git2::Patch::from_buffers(
+            old_blob.content(),
+            Some(&self.filename),
+            new_content.as_bytes(),
+            Some(&self.filename),
+            None,
+        )?;
+
+        let mut lines: Vec<String> = vec![];
+        for hunk_index in 0..patch.num_hunks() {
+            let (hunk, line_count) = patch.hunk(hunk_index)?;
+            lines.push(
+                std::str::from_utf8(hunk.header())
+                    .map_err(|_| SnippetError::NotUtf8(self.filename.clone()))?
+                    .trim_end()
+                    .to_string(),
+            );
+
+            for line_index in 0..line_count {
+                let line = patch.line_in_hunk(hunk_index, line_index)?;
+                lines.push(format!(
+                    "{}{}",
+                    line.origin(),
+                    std::str::from_utf8(line.content())
+                        .map_err(|_| SnippetError::NotUtf8(self.filename.clone()))?
+                        .trim_end()
+                ));
+            }
+        }
+
+        let mut config = self.config;
+        if config.language.is_none() {
+            config.language = Some(String::from("diff"));
+        }
+
+        Ok(Text {
+            hash: self.hash,
+            // Filled in by get_text, where the resolved commit is at hand
+            oid: Oid::zero(),
+            filename: self.filename,
+            scopes: vec![],
+            blame_info: None,
+            caption: None,
+            repo_url: crate::config::repo_url().to_string(),
+            bodies: vec![Body {
+                first: 1,
+                last: lines.len().max(1),
+                lines,
+            }],
+            config,
+        })
+    }
+
+    /// Resolve a snippet whose filename is a directory by concatenating every file directly
+    /// inside it, in sorted order, each prefixed by its own filename info comment.
+    ///
+    /// The per-file info line is numbered 0 so that each file's own lines keep their real line
+    /// numbers. Scope detection makes no sense across files, so it's skipped entirely, as are
+    /// line ranges and subdirectories.
+    fn get_directory_text(self, files: Vec<(PathBuf, String)>) -> Result<Text, SnippetError> {
+        if self.line_ranges.is_some() {
+            return Err(SnippetError::Other(format!(
+                "Line ranges can't be used with the directory snippet {}",
+                self.filename.display()
+            )));
+        }
+
+        let mut bodies: Vec<Body> = vec![];
+        for (path, content) in &files {
+            let mut lines = vec![self
+                .config
+                .info_comment_syntax
+                .wrap(&path.display().to_string())];
+            lines.extend(content.lines().map(String::from));
+
+            bodies.push(Body {
+                first: 0,
+                last: lines.len() - 1,
+                lines,
+            });
+        }
+
+        if bodies.is_empty() {
+            return Err(SnippetError::Other(format!(
+                "Directory {} has no files at {}",
+                self.filename.display(),
+                short_hash(&self.hash)
+            )));
+        }
+
+        let mut config = self.config;
+        if config.language.is_none() {
+            config.language = Some(String::from("python"));
+        }
+
+        Ok(Text {
+            hash: self.hash,
+            // Filled in by get_text, where the resolved commit is at hand
+            oid: Oid::zero(),
+            filename: self.filename,
+            scopes: vec![],
+            blame_info: None,
+            caption: None,
+            repo_url: crate::config::repo_url().to_string(),
+            bodies,
+            config,
+        })
+    }
+}
+
+/// Decode a blob's content, treating it as UTF-8 with an optional ``--encoding`` fallback.
+fn decode_blob(content: &[u8], path: &Path) -> Result<String, SnippetError> {
+    if let Ok(text) = std::str::from_utf8(content) {
+        return Ok(text.to_string());
+    }
+
+    let Some(encoding) = crate::config::encoding() else {
+        return Err(SnippetError::NotUtf8(path.to_path_buf()));
+    };
+
+    let (decoded, _, had_errors) = encoding.decode(content);
+    if had_errors {
+        return Err(SnippetError::Other(format!(
+            "{} is not valid {}",
+            path.display(),
+            encoding.name()
+        )));
+    }
+    Ok(decoded.into_owned())
+}
+
+/// Grow the given 1-based range to cover the whole ``def``/``class`` block enclosing it.
+///
+/// This reuses the indentation rules of [`find_scopes`]: the nearest less-indented ``def`` or
+/// ``class`` line above the range is the block header, and the block runs until the next
+/// non-blank line indented at or below the header. A range with no enclosing block is returned
+/// unchanged.
+fn expand_to_scope(lines: &[&str], first: usize, last: usize) -> (usize, usize) {
+    // The first non-blank line of the range anchors the indentation search
+    let Some(anchor_indent) = lines[(first - 1)..last]
+        .iter()
+        .find(|line| !line.trim().is_empty())
+        .map(|line| indent_depth(line))
+    else {
+        return (first, last);
+    };
+
+    let mut header = None;
+    for (index, &line) in lines[..(first - 1)].iter().enumerate().rev() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let trimmed = line.trim_start();
+        if indent_depth(line) < anchor_indent
+            && (trimmed.starts_with("def ") || trimmed.starts_with("class "))
+        {
+            header = Some((index + 1, indent_depth(line)));
+            break;
+        }
+    }
+    let Some((header_line, header_indent)) = header else {
+        return (first, last);
+    };
+
+    let mut end = last.max(header_line);
+    for (index, &line) in lines.iter().enumerate().skip(header_line) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if indent_depth(line) <= header_indent {
+            break;
+        }
+        end = index + 1;
+    }
+
+    (header_line, end)
+}
+
+/// Find the 1-based line range between the ``SNIPPET-START name`` and ``SNIPPET-END name``
+/// marker lines, including the markers themselves only when `show_markers` is set.
+fn find_marker_range(
+    lines: &[&str],
+    name: &str,
+    filename: &Path,
+    show_markers: bool,
+) -> Result<LineRange, SnippetError> {
+    let start = lines
+        .iter()
+        .position(|line| line.contains(&format!("SNIPPET-START {name}")))
+        .ok_or_else(|| {
+            SnippetError::Other(format!(
+                "Couldn't find marker \"SNIPPET-START {name}\" in {}",
+                filename.display()
+            ))
+        })?;
+    let end = lines
+        .iter()
+        .position(|line| line.contains(&format!("SNIPPET-END {name}")))
+        .ok_or_else(|| {
+            SnippetError::Other(format!(
+                "Couldn't find marker \"SNIPPET-END {name}\" in {}",
+                filename.display()
+            ))
+        })?;
+
+    if end <= start + 1 {
+        return Err(SnippetError::Other(format!(
+            "Markers \"{name}\" in {} are unbalanced or delimit no lines",
+            filename.display()
+        )));
+    }
+
+    Ok(if show_markers {
+        LineRange::Absolute(start + 1, end + 1)
+    } else {
+        LineRange::Absolute(start + 2, end)
+    })
+}
+
+/// Find every file in the given tree with the same basename as the given filename.
+///
+/// Used to suggest (or, with ``--follow-renames``, transparently resolve) the new path of a file
+/// that was renamed since its snippet comment was written.
+fn find_files_with_basename(tree: &git2::Tree, filename: &Path) -> Vec<PathBuf> {
+    let Some(basename) = filename.file_name().and_then(|name| name.to_str()) else {
+        return vec![];
+    };
+
+    let mut candidates = vec![];
+    let _ = tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+        if entry.name() == Some(basename) {
+            candidates.push(PathBuf::from(root).join(basename));
+        }
+        git2::TreeWalkResult::Ok
+    });
+    candidates
+}
+
+/// Infer a Pygments language from the extension of the given filename.
+///
+/// Used when a snippet gives no explicit ``language=`` option. Unknown extensions fall back to
+/// ``python``, which matches the historical default.
+fn infer_language(filename: &Path) -> String {
+    let language = match filename.extension().and_then(|ext| ext.to_str()) {
+        Some("c" | "h") => "c",
+        Some("cpp" | "hpp") => "cpp",
+        Some("css") => "css",
+        Some("html") => "html",
+        Some("js") => "javascript",
+        Some("json") => "json",
+        Some("md") => "markdown",
+        Some("rs") => "rust",
+        Some("sh") => "bash",
+        Some("tex") => "tex",
+        Some("toml") => "toml",
+        Some("yaml" | "yml") => "yaml",
+        _ => "python",
+    };
+    String::from(language)
+}
+
+/// Return the indentation depth of the given line, counting a space as one column and a tab
+/// as a jump to the next multiple of the configured ``--tab-width``.
+///
+/// Only depth measurement goes through here: the body text keeps its original whitespace, so a
+/// tab-indented file still renders exactly as committed.
+fn indent_depth(line: &str) -> usize {
+    let tab_width = crate::config::tab_width();
+    let mut depth = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => depth += 1,
+            '\t' => depth = (depth / tab_width + 1) * tab_width,
+            _ => break,
+        }
+    }
+    depth
+}
+
+/// Format parsed line ranges back into the comment syntax they were written in.
+fn format_line_ranges(ranges: &[LineRange]) -> String {
+    ranges
+        .iter()
+        .map(|range| match *range {
+            LineRange::Absolute(first, last) if first == last => first.to_string(),
+            LineRange::Absolute(first, last) => format!("{first}-{last}"),
+            LineRange::ToEnd(first) => format!("{first}-$"),
+            LineRange::LastN(n) => format!("-{n}"),
+        })
+        .join(",")
+}
+
+/// Abbreviate a commit hash to eight characters, leaving symbolic refs like tag names (and
+/// anything else too short or non-hex to slice) untouched.
+pub(crate) fn short_hash(hash: &str) -> &str {
+    if hash.len() >= 8 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        &hash[..8]
+    } else {
+        hash
+    }
+}
+
+/// Return the number of unmatched opening parentheses on the given line.
+fn paren_balance(line: &str) -> isize {
+    line.chars()
+        .map(|c| match c {
+            '(' => 1,
+            ')' => -1,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Find the ``def`` and ``class`` lines enclosing the line with the given number.
+///
+/// We walk upwards from the start of the snippet, collecting every ``def`` or ``class`` line with
+/// strictly less indentation than anything collected so far, and then discard any leading
+/// indented scopes, which handles apparent scopes inside module docstrings.
+fn find_scopes(lines: &[&str], first: usize) -> Vec<(usize, String)> {
+    let mut scopes: Vec<(usize, usize, &str)> = vec![];
+    let mut indent = indent_depth(lines[first - 1]);
+
+    for (index, &line) in lines[..(first - 1)].iter().enumerate().rev() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let line_indent = indent_depth(line);
+        let trimmed = line.trim_start();
+
+        if line_indent < indent && (trimmed.starts_with("def ") || trimmed.starts_with("class ")) {
+            indent = line_indent;
+
+            // A wrapped signature leaves the header line with unbalanced parentheses, so its
+            // continuation lines down to the closing ``):`` are part of the header. They're
+            // pushed deepest-first so the final reverse puts them back under the header
+            let mut balance = paren_balance(line);
+            let mut continuation = vec![];
+            let mut continuation_index = index + 1;
+            while balance > 0 && continuation_index < first - 1 {
+                let continuation_line = lines[continuation_index];
+                continuation.push((line_indent, continuation_index + 1, continuation_line));
+                balance += paren_balance(continuation_line);
+                continuation_index += 1;
+            }
+            scopes.extend(continuation.into_iter().rev());
+
+            scopes.push((line_indent, index + 1, line));
+
+            // Decorators carry meaning, so the ones directly above the header belong to it.
+            // They're pushed in upward order here and fall back into place on the reverse below
+            for decorator_index in (0..index).rev() {
+                let decorator = lines[decorator_index];
+                if indent_depth(decorator) == line_indent
+                    && decorator.trim_start().starts_with('@')
+                {
+                    scopes.push((line_indent, decorator_index + 1, decorator));
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+
+    scopes.reverse();
+
+    // The docstring heuristic can also drop legitimate context in unusual files, so say what
+    // it removed when asked
+    let first_top_level = scopes
+        .iter()
+        .position(|&(indent, _, _)| indent == 0)
+        .unwrap_or(scopes.len());
+    if crate::config::verbose() {
+        for &(_, number, line) in &scopes[..first_top_level] {
+            println!("    skipped indented scope candidate at line {number}: {line}");
+        }
+    }
+
+    scopes[first_top_level..]
+        .iter()
+        .map(|&(_, number, line)| (number, line.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::latex_tests::{get_repo, TEST_HASH};
+
+    #[test]
+    fn from_latex_comment_test() {
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56"
+        ))
+        .unwrap();
+        assert_eq!(comment.hash, TEST_HASH);
+        assert_eq!(
+            comment.filename,
+            PathBuf::from("src/lintrans/matrices/wrapper.py")
+        );
+        assert_eq!(comment.line_ranges, Some(vec![LineRange::Absolute(45, 56)]));
+        assert_eq!(comment.config, Config::default());
+
+        let whole_file =
+            Comment::from_latex_comment(&format!("%: {TEST_HASH}\n%: compile.py noscopes"))
+                .unwrap();
+        assert_eq!(whole_file.line_ranges, None);
+        assert!(whole_file.config.noscopes);
+
+        // A trailing colon with no ranges is treated as a whole-file snippet, not a panic
+        let empty_ranges =
+            Comment::from_latex_comment(&format!("%: {TEST_HASH}\n%: compile.py:")).unwrap();
+        assert_eq!(empty_ranges.line_ranges, None);
+
+        // Ranges that don't parse, or only parse partially, don't panic or truncate: the
+        // whole comment is unparseable and gets left untouched
+        assert_eq!(
+            Comment::from_latex_comment(&format!("%: {TEST_HASH}\n%: compile.py:- noscopes")),
+            None
+        );
+        assert_eq!(
+            Comment::from_latex_comment(&format!("%: {TEST_HASH}\n%: compile.py:5- noscopes")),
+            None
+        );
+
+        assert_eq!(Comment::from_latex_comment("%: not a comment"), None);
+    }
+
+    #[test]
+    fn inline_config_comment_test() {
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56\n%: {{ language = \"text\", noscopes = true }}"
+        ))
+        .unwrap();
+        assert_eq!(comment.line_ranges, Some(vec![LineRange::Absolute(45, 56)]));
+        assert_eq!(comment.config.language.as_deref(), Some("text"));
+        assert!(comment.config.noscopes);
+    }
+
+    #[test]
+    fn parse_line_ranges_test() {
+        assert_eq!(
+            parse_line_ranges("45-56").unwrap().1,
+            vec![LineRange::Absolute(45, 56)]
+        );
+        assert_eq!(
+            parse_line_ranges("45-56,60-62,70").unwrap().1,
+            vec![
+                LineRange::Absolute(45, 56),
+                LineRange::Absolute(60, 62),
+                LineRange::Absolute(70, 70)
+            ]
+        );
+        assert_eq!(
+            parse_line_ranges("200-$,-10").unwrap().1,
+            vec![LineRange::ToEnd(200), LineRange::LastN(10)]
+        );
+        assert!(parse_line_ranges("").is_err());
+    }
+
+    #[test]
+    fn end_anchored_range_test() {
+        // .readthedocs.yaml is 28 lines long at the test commit
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: .readthedocs.yaml:-3 noscopes"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!((text.bodies[0].first, text.bodies[0].last), (26, 28));
+
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: .readthedocs.yaml:25-$ noscopes"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!((text.bodies[0].first, text.bodies[0].last), (25, 28));
+    }
+
+    #[test]
+    fn dedent_test() {
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 dedent"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+
+        // The 4-space indentation of the method is stripped, leaving the body's own nesting
+        assert_eq!(text.bodies[0].lines[0], "def __init__(self):");
+        assert!(text.bodies[0].lines[2].starts_with("    self._matrices"));
+
+        // The scope line was already at the margin, so it stays put
+        assert_eq!(
+            text.scopes,
+            vec![(24, String::from("class MatrixWrapper:"))]
+        );
+    }
+
+    #[test]
+    fn manifest_test() {
+        load_manifest(&format!(
+            "# Central snippet definitions\n\n@init\n{TEST_HASH}\nsrc/lintrans/matrices/wrapper.py:45-56 noscopes\n"
+        ))
+        .unwrap();
+
+        let comment = Comment::from_latex_comment("%: @init").unwrap();
+        assert_eq!(comment.hash, TEST_HASH);
+        assert_eq!(comment.line_ranges, Some(vec![LineRange::Absolute(45, 56)]));
+        assert!(comment.config.noscopes);
+
+        assert_eq!(Comment::from_latex_comment("%: @missing"), None);
+    }
+
+    #[test]
+    fn resolve_test() {
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56"
+        ))
+        .unwrap();
+
+        // Resolution borrows the comment, so it can be inspected and then still rendered
+        let repo = get_repo();
+        let resolved = comment.resolve(&repo).unwrap();
+        assert_eq!(resolved.commit.id().to_string(), TEST_HASH);
+        assert!(matches!(resolved.source, ResolvedSource::File(_)));
+
+        comment.get_text(&repo).unwrap();
+    }
+
+    #[test]
+    fn snippet_error_test() {
+        let repo = get_repo();
+
+        // The errors are matchable, not just printable
+        let comment =
+            Comment::from_latex_comment(&format!("%: {}\n%: compile.py", "a".repeat(40))).unwrap();
+        assert!(matches!(
+            comment.get_text(&repo),
+            Err(SnippetError::MissingCommit(_))
+        ));
+
+        let comment =
+            Comment::from_latex_comment(&format!("%: {TEST_HASH}\n%: nonexistent.py")).unwrap();
+        let error = comment.get_text(&repo).unwrap_err();
+        assert!(matches!(error, SnippetError::MissingFile { .. }));
+        assert_eq!(error.to_string(), "Couldn't find nonexistent.py at 48a16577");
+    }
+
+    #[test]
+    fn marker_error_test() {
+        let repo = get_repo();
+
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: misc/marker_example.py marker=missing"
+        ))
+        .unwrap();
+        assert!(comment
+            .get_text(&repo)
+            .unwrap_err()
+            .to_string()
+            .contains("SNIPPET-START missing"));
+
+        // A pair with no lines between the markers is as useless as a missing one
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: misc/marker_example.py marker=empty"
+        ))
+        .unwrap();
+        assert!(comment.get_text(&repo).is_err());
+    }
+
+    #[test]
+    fn decorator_scope_test() {
+        // The decorator directly above the enclosing def is part of that scope entry
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: misc/decorated_example.py:9"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!(
+            text.scopes,
+            vec![
+                (4, String::from("class Widget:")),
+                (7, String::from("    @property")),
+                (8, String::from("    def size(self):")),
+            ]
+        );
+    }
+
+    #[test]
+    fn wrapped_signature_scope_test() {
+        // The enclosing signature spans four lines, and all of them show as the scope header
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: misc/decorated_example.py:18"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!(
+            text.scopes,
+            vec![
+                (4, String::from("class Widget:")),
+                (11, String::from("    @staticmethod")),
+                (12, String::from("    @functools.cache")),
+                (13, String::from("    def expensive(")),
+                (14, String::from("        first,")),
+                (15, String::from("        second,")),
+                (16, String::from("    ):")),
+            ]
+        );
+    }
+
+    #[test]
+    fn short_hash_test() {
+        // An abbreviated hash resolves to its unique commit; an unknown prefix reports cleanly
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {}\n%: compile.py noscopes",
+            &TEST_HASH[..12]
+        ))
+        .unwrap();
+        assert!(comment.get_text(&get_repo()).is_ok());
+
+        let comment =
+            Comment::from_latex_comment("%: 0123456789ab\n%: compile.py noscopes").unwrap();
+        let error = comment.get_text(&get_repo()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Couldn't find commit 0123456789ab in the repo"
+        );
+    }
+
+    #[test]
+    fn tab_indent_scope_test() {
+        // Tabs expand to depth 8, so the tab-indented def sits between the class and the
+        // tab-plus-spaces body instead of all three measuring as depth 0
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: misc/tab_example.py:6"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!(
+            text.scopes,
+            vec![
+                (4, String::from("class Grid:")),
+                (5, String::from("\tdef draw(self):")),
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_to_scope_test() {
+        // A single line inside __init__ grows to the whole method, header included
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:47 expand_to_scope noscopes"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!((text.bodies[0].first, text.bodies[0].last), (45, 56));
+    }
+
+    #[test]
+    fn encoding_test() {
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: misc/latin1_example.py:3 noscopes"
+        ))
+        .unwrap();
+
+        // Without a fallback encoding, the Latin-1 bytes are simply not UTF-8...
+        // (the OnceLock means this assertion must come before set_encoding below)
+        assert!(matches!(
+            comment.clone().get_text(&get_repo()),
+            Err(SnippetError::NotUtf8(_))
+        ));
+
+        // ...but with one, the accented characters decode properly
+        crate::config::set_encoding("latin1").unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!(text.bodies[0].lines[0], "ACCENT = \"caf\u{e9}\"");
+    }
+
+    #[test]
+    fn crlf_test() {
+        // The fixture file is CRLF-terminated, and the endings survive into the body lines
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: misc/crlf_example.py:3-4 noscopes"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert!(text.bodies[0].lines.iter().all(|line| line.ends_with('\r')));
+    }
+
+    #[test]
+    fn strip_copyright_comment_test() {
+        // compile.py starts with a shebang, so the whole header is 8 lines
+        let comment =
+            Comment::from_latex_comment(&format!("%: {TEST_HASH}\n%: compile.py noscopes"))
+                .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!(text.bodies[0].first, 9);
+        assert!(text.bodies[0].lines[0].starts_with("\"\"\"A simple compile script"));
+
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: compile.py keep_copyright_comment noscopes"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!(text.bodies[0].first, 1);
+
+        // keep_copyright_blank keeps the blank line that separates the header from the code
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: compile.py keep_copyright_blank noscopes"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!(text.bodies[0].first, 8);
+        assert!(text.bodies[0].lines[0].is_empty());
+    }
+
+    #[test]
+    fn to_comment_string_test() {
+        // The canonical form re-parses to an equal comment, and canonicalizing it again
+        // changes nothing
+        for text in [
+            format!("%: {TEST_HASH}\n%: compile.py"),
+            format!(
+                "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45,55-56 \
+                 noscopes highlight=47"
+            ),
+            format!("%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:10-$,-3 noscopes"),
+        ] {
+            let comment = Comment::from_latex_comment(&text).unwrap();
+            let canonical = comment.to_comment_string();
+            assert_eq!(Comment::from_latex_comment(&canonical).unwrap(), comment);
+            assert_eq!(
+                Comment::from_latex_comment(&canonical)
+                    .unwrap()
+                    .to_comment_string(),
+                canonical
+            );
+        }
+
+        // A space-containing value from an inline TOML table can't survive the flat syntax,
+        // so the round-trip fails; --canonicalize checks this and leaves the comment alone
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: compile.py noscopes\n\
+             %: {{ language = \"lexers.py:MyLexer -x\" }}"
+        ))
+        .unwrap();
+        assert_ne!(
+            Comment::from_latex_comment(&comment.to_comment_string()),
+            Some(comment)
+        );
+    }
+
+    #[test]
+    fn keep_shebang_test() {
+        // keep_shebang pulls the shebang line back out of the stripped header, as its own
+        // one-line body with the usual gap down to the code
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: compile.py keep_shebang noscopes"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!(text.bodies.len(), 2);
+        assert_eq!((text.bodies[0].first, text.bodies[0].last), (1, 1));
+        assert_eq!(text.bodies[0].lines, vec!["#!/usr/bin/env python"]);
+        assert_eq!(text.bodies[1].first, 9);
+
+        // With the copyright comment kept there's nothing to re-prepend
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: compile.py keep_shebang keep_copyright_comment noscopes"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!(text.bodies.len(), 1);
+        assert_eq!(text.bodies[0].first, 1);
+    }
+
+    #[test]
+    fn merge_overlapping_ranges_test() {
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:10-20,15-25 noscopes"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!(text.bodies.len(), 1);
+        assert_eq!((text.bodies[0].first, text.bodies[0].last), (10, 25));
+
+        // Adjacent ranges are coalesced too, since there's no gap to indicate
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45,46-47 noscopes"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!(text.bodies.len(), 1);
+        assert_eq!((text.bodies[0].first, text.bodies[0].last), (45, 47));
+    }
+
+    #[test]
+    fn context_test() {
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 context=2 noscopes"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!((text.bodies[0].first, text.bodies[0].last), (43, 58));
+
+        // The expansion is clamped to the bounds of the file
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: .readthedocs.yaml:1-3 context=5 noscopes"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!((text.bodies[0].first, text.bodies[0].last), (1, 8));
+    }
+
+    #[test]
+    fn trim_blank_body_edges_test() {
+        // Lines 44 and 57 are blank, so trimming leaves exactly the method on lines 45-56
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:44-57 trim_blank_body_edges"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!((text.bodies[0].first, text.bodies[0].last), (45, 56));
+        assert!(text.bodies[0].lines[0].ends_with("def __init__(self):"));
+        assert_eq!(text.bodies[0].lines.len(), 12);
+    }
+
+    #[test]
+    fn infer_language_test() {
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: .readthedocs.yaml:1-3 noscopes"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!(text.config.language.as_deref(), Some("yaml"));
+
+        // An explicit language always overrides the inference
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: .readthedocs.yaml:1-3 language=text noscopes"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!(text.config.language.as_deref(), Some("text"));
+    }
+
+    #[test]
+    fn find_scopes_test() {
+        let comment = Comment::from_latex_comment(&format!(
+            "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56"
+        ))
+        .unwrap();
+        let text = comment.get_text(&get_repo()).unwrap();
+        assert_eq!(
+            text.scopes,
+            vec![(24, String::from("class MatrixWrapper:"))]
+        );
+    }
+}
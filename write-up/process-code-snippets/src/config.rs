@@ -0,0 +1,1659 @@
+// lintrans - The linear transformation visualizer
+// Copyright (C) 2021-2022 D. Dyson (DoctorDalek1963)
+
+// This program is licensed under GNU GPLv3, available here:
+// <https://www.gnu.org/licenses/gpl-3.0.html>
+
+//! This module provides the [`Config`] struct to hold per-snippet configuration, along with the
+//! nom parsers that read the options from the end of a snippet comment.
+
+use color_eyre::{eyre::eyre, Result};
+use lazy_static::lazy_static;
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_till, take_till1},
+    character::complete::{char, u64 as nom_u64},
+    combinator::{map, map_opt},
+    multi::separated_list0,
+    sequence::{delimited, preceded},
+    IResult,
+};
+use regex::Regex;
+use serde::Deserialize;
+use std::{collections::HashMap, sync::OnceLock};
+
+lazy_static! {
+    /// The pattern used to split a `comment=` value into the parts before and after the ``{}``.
+    static ref COMMENT_SYNTAX_PATTERN: Regex = Regex::new(r"^([^{}]*)\{\}([^{}]*)$").unwrap();
+}
+
+/// The custom macros defined in the project's ``.snippets.toml`` file, if any.
+static CUSTOM_MACROS: OnceLock<HashMap<String, CustomMacro>> = OnceLock::new();
+
+/// The configured copyright comment pattern, if any.
+static COPYRIGHT_PATTERN: OnceLock<Regex> = OnceLock::new();
+
+/// The project-wide default config that sits beneath every snippet's own options, if any.
+static DEFAULT_CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Whether snippets may transparently resolve files that were renamed in the repo.
+static FOLLOW_RENAMES: OnceLock<bool> = OnceLock::new();
+
+/// Whether CRLF line endings in source blobs should be normalized to LF.
+static NORMALIZE_EOL: OnceLock<bool> = OnceLock::new();
+
+/// Whether snippet languages should be checked against the known Pygments aliases.
+static CHECK_LANGUAGES: OnceLock<bool> = OnceLock::new();
+
+/// The fallback encoding for blobs that aren't valid UTF-8, if one has been configured.
+static ENCODING: OnceLock<&'static encoding_rs::Encoding> = OnceLock::new();
+
+/// Whether verbose output was requested, for diagnostics printed below the comment layer.
+static VERBOSE: OnceLock<bool> = OnceLock::new();
+
+/// The base URL of the repo on GitHub (or a mirror), if one has been configured.
+static REPO_URL: OnceLock<String> = OnceLock::new();
+
+/// The tab width used when measuring indentation, if one has been configured.
+static TAB_WIDTH: OnceLock<usize> = OnceLock::new();
+
+/// Whether each snippet's raw body should be written to a sidecar file next to the output.
+static EMIT_SIDECARS: OnceLock<bool> = OnceLock::new();
+
+/// The maximum number of body lines a snippet may have, if a limit has been configured.
+static MAX_LINES: OnceLock<usize> = OnceLock::new();
+
+/// The expansion of a custom macro defined in a project config file.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct CustomMacro {
+    /// The language to set, if any.
+    pub language: Option<String>,
+
+    /// The info comment syntax to set, as a template like ``// {}``, if any.
+    pub comment: Option<String>,
+
+    /// The default highlight lines to set, if any.
+    pub highlight: Option<String>,
+}
+
+/// The structure of a ``.snippets.toml`` project config file.
+#[derive(Debug, Deserialize)]
+struct ProjectConfig {
+    /// The custom macros, keyed by their name without the trailing ``!``.
+    #[serde(default)]
+    macros: HashMap<String, CustomMacro>,
+
+    /// The regex matching the copyright comment at the top of the project's source files.
+    copyright_pattern: Option<String>,
+
+    /// Default snippet options applied beneath every comment's own, in the usual option
+    /// syntax like ``linenumcolor=gray noscopes``. Per-snippet options win.
+    defaults: Option<String>,
+}
+
+/// Load a project's ``.snippets.toml`` file, registering its custom macros for
+/// [`ConfigMacro::parse`] and its copyright comment pattern, if it has one.
+///
+/// Loading twice has no effect, so this should be called once, before any config is parsed.
+pub fn load_project_config(text: &str) -> Result<()> {
+    let project_config: ProjectConfig = toml::from_str(text)?;
+    // A bad comment template in a macro would otherwise only surface when the macro is used
+    for (name, custom) in &project_config.macros {
+        if let Some(comment) = &custom.comment {
+            InfoCommentSyntax::parse(comment)
+                .map_err(|e| eyre!("In custom macro {name:?}: {e}"))?;
+        }
+    }
+    let _ = CUSTOM_MACROS.set(project_config.macros);
+    if let Some(pattern) = project_config.copyright_pattern {
+        set_copyright_pattern(&pattern)?;
+    }
+    // The macros are registered above first, so the defaults can use them too
+    if let Some(defaults) = project_config.defaults {
+        let _ = DEFAULT_CONFIG.set(Config::parse_with_base(&defaults, Config::default())?);
+    }
+    Ok(())
+}
+
+/// Set the copyright comment pattern, overriding the default lintrans one.
+///
+/// Setting twice has no effect, so a pattern given on the command line should be set before the
+/// project config is loaded.
+pub fn set_copyright_pattern(pattern: &str) -> Result<()> {
+    let _ = COPYRIGHT_PATTERN.set(Regex::new(pattern)?);
+    Ok(())
+}
+
+/// Return the configured copyright comment pattern, if one has been set.
+pub fn copyright_pattern() -> Option<&'static Regex> {
+    COPYRIGHT_PATTERN.get()
+}
+
+/// Allow snippets to transparently resolve files that were renamed in the repo.
+pub fn set_follow_renames() {
+    let _ = FOLLOW_RENAMES.set(true);
+}
+
+/// Return whether snippets may transparently resolve renamed files.
+pub fn follow_renames() -> bool {
+    *FOLLOW_RENAMES.get().unwrap_or(&false)
+}
+
+/// Normalize CRLF line endings in source blobs to LF instead of preserving them.
+pub fn set_normalize_eol() {
+    let _ = NORMALIZE_EOL.set(true);
+}
+
+/// Return whether CRLF line endings should be normalized to LF.
+pub fn normalize_eol() -> bool {
+    *NORMALIZE_EOL.get().unwrap_or(&false)
+}
+
+/// Warn about snippet languages that aren't known Pygments aliases.
+pub fn set_check_languages() {
+    let _ = CHECK_LANGUAGES.set(true);
+}
+
+/// Enable verbose diagnostics in the resolution code, set by ``--verbose``.
+pub fn set_verbose() {
+    let _ = VERBOSE.set(true);
+}
+
+/// Return whether verbose diagnostics were requested.
+pub fn verbose() -> bool {
+    *VERBOSE.get().unwrap_or(&false)
+}
+
+/// Return whether snippet languages should be checked against the known Pygments aliases.
+pub fn check_languages() -> bool {
+    *CHECK_LANGUAGES.get().unwrap_or(&false)
+}
+
+/// Set the fallback encoding for blobs that aren't valid UTF-8, by a WHATWG label like
+/// ``latin1`` or ``windows-1252``.
+pub fn set_encoding(label: &str) -> Result<()> {
+    let encoding = encoding_rs::Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| eyre!("Unknown encoding {label:?}"))?;
+    let _ = ENCODING.set(encoding);
+    Ok(())
+}
+
+/// Return the fallback encoding for blobs that aren't valid UTF-8, if one has been configured.
+pub fn encoding() -> Option<&'static encoding_rs::Encoding> {
+    ENCODING.get().copied()
+}
+
+/// Set the base URL that URL-emitting features build links from, overriding the default
+/// lintrans GitHub URL.
+///
+/// The URL should have no trailing slash; one is stripped if present, since every consumer
+/// appends its own ``/``-prefixed path.
+pub fn set_repo_url(url: &str) {
+    let _ = REPO_URL.set(url.trim_end_matches('/').to_string());
+}
+
+/// Return the base URL that URL-emitting features build links from.
+pub fn repo_url() -> &'static str {
+    REPO_URL
+        .get()
+        .map(String::as_str)
+        .unwrap_or("https://github.com/DoctorDalek1963/lintrans")
+}
+
+/// Set the tab width used when measuring indentation, overriding the default of 8.
+pub fn set_tab_width(width: usize) {
+    let _ = TAB_WIDTH.set(width);
+}
+
+/// Return the tab width used when measuring indentation.
+pub fn tab_width() -> usize {
+    *TAB_WIDTH.get().unwrap_or(&8)
+}
+
+/// Write each snippet's raw body to a copyable sidecar file next to the output.
+pub fn set_emit_sidecars() {
+    let _ = EMIT_SIDECARS.set(true);
+}
+
+/// Return whether each snippet's raw body should be written to a sidecar file.
+pub fn emit_sidecars() -> bool {
+    *EMIT_SIDECARS.get().unwrap_or(&false)
+}
+
+/// Set the maximum number of body lines a snippet may have before a warning is emitted.
+pub fn set_max_lines(max: usize) {
+    let _ = MAX_LINES.set(max);
+}
+
+/// Return the maximum number of body lines a snippet may have, if a limit has been configured.
+pub fn max_lines() -> Option<usize> {
+    MAX_LINES.get().copied()
+}
+
+/// The syntax used to wrap the info comment lines at the top of a snippet.
+///
+/// The info comment holds the commit hash and filename, and must be wrapped in the comment syntax
+/// of the snippet's language. The syntax is written as a template like ``# {}``, where the ``{}``
+/// is replaced by the info text. A block comment syntax can span multiple lines by using ``\n``
+/// escapes in the template, like ``/*\n * {}\n */``; the extra lines are accounted for in the
+/// line numbering.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InfoCommentSyntax {
+    /// The text that comes before the info text.
+    pub before: String,
+
+    /// The text that comes after the info text.
+    pub after: String,
+}
+
+impl Default for InfoCommentSyntax {
+    fn default() -> Self {
+        Self {
+            before: String::from("# "),
+            after: String::new(),
+        }
+    }
+}
+
+impl InfoCommentSyntax {
+    /// Parse an info comment syntax from a template like ``# {}`` or ``/*\n * {}\n */``.
+    ///
+    /// The template must contain exactly one ``{}`` placeholder and no other braces; anything
+    /// else (most easily a forgotten ``{}``) is an error rather than a panic.
+    pub fn parse(text: &str) -> Result<Self> {
+        let captures = COMMENT_SYNTAX_PATTERN
+            .captures(text)
+            .ok_or_else(|| eyre!("Comment syntax {text:?} must contain exactly one {{}}"))?;
+        Ok(Self {
+            before: captures[1].replace("\\n", "\n"),
+            after: captures[2].replace("\\n", "\n"),
+        })
+    }
+
+    /// Wrap the given info text in this comment syntax.
+    pub fn wrap(&self, text: &str) -> String {
+        format!("{}{}{}", self.before, text, self.after)
+    }
+}
+
+/// A macro that a snippet comment can use to set several config options at once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigMacro {
+    /// The ``c!`` macro, which sets ``language=c`` and ``comment="// {}"``.
+    C,
+
+    /// The ``cpp!`` macro, which sets ``language=cpp`` and ``comment="// {}"``.
+    Cpp,
+
+    /// The ``markdown!`` macro, which sets ``language=markdown`` and ``comment="<!-- {} -->"``.
+    Markdown,
+
+    /// The ``rust!`` macro, which sets ``language=rust`` and ``comment="// {}"``.
+    Rust,
+
+    /// A custom macro defined in the project's ``.snippets.toml`` file.
+    Custom(String, CustomMacro),
+}
+
+impl ConfigMacro {
+    /// Parse a config macro from its name, like ``markdown!``.
+    ///
+    /// Custom macros loaded with [`load_project_config`] are consulted after the built-ins, so a
+    /// built-in macro always wins over a custom one with the same name.
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "c!" => Some(Self::C),
+            "cpp!" => Some(Self::Cpp),
+            "markdown!" => Some(Self::Markdown),
+            "rust!" => Some(Self::Rust),
+            _ => {
+                let name = text.strip_suffix('!')?;
+                let custom = CUSTOM_MACROS.get()?.get(name)?;
+                Some(Self::Custom(name.to_string(), custom.clone()))
+            }
+        }
+    }
+
+    /// Return the name of this macro, as written in a snippet comment.
+    pub fn name(&self) -> String {
+        match self {
+            Self::C => String::from("c!"),
+            Self::Cpp => String::from("cpp!"),
+            Self::Markdown => String::from("markdown!"),
+            Self::Rust => String::from("rust!"),
+            Self::Custom(name, _) => format!("{name}!"),
+        }
+    }
+
+    /// Apply this macro to the given config.
+    pub fn apply(&self, config: &mut Config) {
+        let (language, before, after) = match self {
+            Self::C => ("c", "// ", ""),
+            Self::Cpp => ("cpp", "// ", ""),
+            Self::Markdown => ("markdown", "<!-- ", " -->"),
+            Self::Rust => ("rust", "// ", ""),
+            Self::Custom(_, custom) => {
+                if let Some(language) = &custom.language {
+                    config.language = Some(language.clone());
+                }
+                if let Some(comment) = &custom.comment {
+                    config.info_comment_syntax = InfoCommentSyntax::parse(comment)
+                        .expect("Custom macro comment syntax is validated at load time");
+                }
+                if let Some(highlight) = &custom.highlight {
+                    config.highlight_lines = Some(highlight.clone());
+                }
+                return;
+            }
+        };
+        config.language = Some(String::from(language));
+        config.info_comment_syntax = InfoCommentSyntax {
+            before: String::from(before),
+            after: String::from(after),
+        };
+    }
+}
+
+/// A single option parsed from the end of a snippet comment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigOption {
+    /// A macro like ``markdown!``.
+    Macro(ConfigMacro),
+
+    /// ``allow_long``, letting a snippet exceed the ``--max-lines`` limit.
+    AllowLong,
+
+    /// ``autogobble``, letting minted strip the common whitespace prefix at render time.
+    Autogobble,
+
+    /// ``backend=verbatim``, rendering with fancyvrb instead of minted.
+    Backend(Backend),
+
+    /// ``blame``, listing the commits that last touched the snippet lines in the info comment.
+    Blame,
+
+    /// ``breakanywhere``, letting minted break long lines at any character.
+    BreakAnywhere,
+
+    /// ``breaklines``, letting minted break long lines at spaces.
+    BreakLines,
+
+    /// ``caption=commit`` or ``caption="..."``, captioning the snippet.
+    Caption(Caption),
+
+    /// ``comment="..."``, setting the info comment syntax. The template is kept raw here and
+    /// parsed in [`Config::parse_with_base`], which can report a bad template properly.
+    Comment(String),
+
+    /// ``compact_scopes``, collapsing the scope headers onto a single line above the body.
+    CompactScopes,
+
+    /// ``context=N``, expanding each line range by N lines on each side.
+    Context(usize),
+
+    /// ``dedent``, stripping the common indentation from the snippet body.
+    Dedent,
+
+    /// ``diff=<hash>``, rendering a unified diff against the same file at another commit.
+    Diff(String),
+
+    /// ``elide=25-35``, splitting each range around an elided interior.
+    Elide(String),
+
+    /// ``exclude=15,22``, dropping single lines from inside the displayed ranges.
+    Exclude(String),
+
+    /// ``ellipsis="..."``, setting the gap indicator text shown between chunks.
+    Ellipsis(String),
+
+    /// ``expand_to_scope``, growing each range to the whole enclosing ``def``/``class`` block.
+    ExpandToScope,
+
+    /// ``firstnumber=N``, rebasing the displayed numbers so the first body line shows N.
+    FirstNumber(usize),
+
+    /// ``float`` or ``float=htbp``, wrapping the snippet in a ``listing`` float.
+    Float(String),
+
+    /// ``frame=single``, passing ``frame=single`` to minted to draw a frame around the
+    /// listing.
+    Frame(String),
+
+    /// ``framesep=2mm``, passing ``framesep=2mm`` to minted to set the gap between the frame
+    /// and the code.
+    FrameSep(String),
+
+    /// ``gobble=N``, passing ``gobble=N`` to minted to strip exactly N characters per line.
+    Gobble(usize),
+
+    /// ``hide_markers``, excluding the marker lines of a ``marker=`` range (the default).
+    HideMarkers,
+
+    /// ``highlight=...``, setting the lines to pass to minted's ``highlightlines``.
+    Highlight(String),
+
+    /// ``highlight_diff=<hash>``, highlighting the lines changed since another commit.
+    HighlightDiff(String),
+
+    /// ``highlight_regex="..."``, highlighting every body line that matches a regex.
+    HighlightRegex(String),
+
+    /// ``highlight_rel=...``, highlighting lines counted within the displayed snippet body.
+    HighlightRel(String),
+
+    /// ``info_as_caption``, moving the provenance info into the minted caption instead of
+    /// emitting it as comment lines inside the listing.
+    InfoAsCaption,
+
+    /// ``info_position=trailing``, appending the provenance info to the first body line
+    /// instead of emitting separate header lines.
+    InfoPosition(InfoPosition),
+
+    /// ``keep_copyright_blank``, keeping the blank line after a stripped copyright comment.
+    KeepCopyrightBlank,
+
+    /// ``keep_copyright_comment``, keeping the copyright comment in whole-file snippets.
+    KeepCopyrightComment,
+
+    /// ``keep_shebang``, keeping a leading ``#!`` line even when the copyright comment below
+    /// it is stripped.
+    KeepShebang,
+
+    /// ``langs=python,sql``, setting one language per line range.
+    Langs(String),
+
+    /// ``language=...``, setting the language of the ``minted`` environment.
+    Language(String),
+
+    /// ``linenumcolor=...``, setting the color of the line-number gutter.
+    LineNumColor(String),
+
+    /// ``marker=name``, selecting the lines between the ``SNIPPET-START name`` and
+    /// ``SNIPPET-END name`` markers in the file itself.
+    Marker(String),
+
+    /// ``no_separator``, dropping the blank line between the info comments and the body.
+    NoSeparator,
+
+    /// ``noinfo``, omitting the info comment lines above the snippet entirely.
+    NoInfo,
+
+    /// ``noscopes``, suppressing the scope lines above the snippet body.
+    NoScopes,
+
+    /// ``numbersep=12pt``, passing ``numbersep=12pt`` to minted to set the gap between the
+    /// line numbers and the code.
+    NumberSep(String),
+
+    /// ``renumber``, displaying sequential line numbers from 1 instead of file line numbers.
+    Renumber,
+
+    /// ``scope_header="..."``, showing a literal scope header line instead of detected ones.
+    ScopeHeader(String),
+
+    /// ``show_markers``, including the marker lines of a ``marker=`` range.
+    ShowMarkers,
+
+    /// ``stepnumber=N``, showing a line number only on every Nth line.
+    StepNumber(usize),
+
+    /// ``trim_blank_body_edges``, dropping blank lines from the edges of each body.
+    TrimBlankBodyEdges,
+}
+
+/// The LaTeX environment used to render a snippet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// The default ``minted`` environment, with Pygments highlighting.
+    #[default]
+    Minted,
+
+    /// A plain fancyvrb ``Verbatim`` environment, for toolchains without Pygments.
+    Verbatim,
+}
+
+/// Where the provenance info comment goes in the rendered snippet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InfoPosition {
+    /// The default header lines above the body, offset out of the line numbering.
+    #[default]
+    Leading,
+
+    /// A single comment appended to the end of the first body line, which needs no line
+    /// number juggling at all.
+    Trailing,
+}
+
+/// The source of a snippet's caption.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Caption {
+    /// Caption with the summary line of the snippet's commit.
+    Commit,
+
+    /// Caption with a literal string.
+    Literal(String),
+}
+
+/// Parse a single config option.
+///
+/// The options are split across nested ``alt`` calls because nom's ``alt`` only accepts a
+/// bounded number of alternatives in one tuple.
+fn config_option(input: &str) -> IResult<&str, ConfigOption> {
+    alt((
+        alt((
+            map(
+                map_opt(take_till1(|c| c == ' '), ConfigMacro::parse),
+                ConfigOption::Macro,
+            ),
+            map(tag("allow_long"), |_| ConfigOption::AllowLong),
+            map(tag("autogobble"), |_| ConfigOption::Autogobble),
+            map(tag("backend=verbatim"), |_| {
+                ConfigOption::Backend(Backend::Verbatim)
+            }),
+            map(tag("blame"), |_| ConfigOption::Blame),
+            map(tag("breakanywhere"), |_| ConfigOption::BreakAnywhere),
+            map(tag("breaklines"), |_| ConfigOption::BreakLines),
+            map(tag("caption=commit"), |_| {
+                ConfigOption::Caption(Caption::Commit)
+            }),
+            map(
+                delimited(tag("caption=\""), take_till(|c| c == '"'), char('"')),
+                |caption: &str| ConfigOption::Caption(Caption::Literal(caption.to_string())),
+            ),
+            map(
+                delimited(tag("comment=\""), take_till(|c| c == '"'), char('"')),
+                |syntax: &str| ConfigOption::Comment(syntax.to_string()),
+            ),
+            map(tag("compact_scopes"), |_| ConfigOption::CompactScopes),
+            map(preceded(tag("context="), nom_u64), |n| {
+                ConfigOption::Context(n as usize)
+            }),
+            map(tag("dedent"), |_| ConfigOption::Dedent),
+            map(
+                preceded(tag("diff="), take_till1(|c| c == ' ')),
+                |hash: &str| ConfigOption::Diff(hash.to_string()),
+            ),
+            map(
+                preceded(tag("elide="), take_till1(|c| c == ' ')),
+                |range: &str| ConfigOption::Elide(range.to_string()),
+            ),
+            map(
+                delimited(tag("ellipsis=\""), take_till(|c| c == '"'), char('"')),
+                |ellipsis: &str| ConfigOption::Ellipsis(ellipsis.to_string()),
+            ),
+            map(
+                preceded(tag("exclude="), take_till1(|c| c == ' ')),
+                |lines: &str| ConfigOption::Exclude(lines.to_string()),
+            ),
+            map(tag("expand_to_scope"), |_| ConfigOption::ExpandToScope),
+            map(preceded(tag("firstnumber="), nom_u64), |n| {
+                ConfigOption::FirstNumber(n as usize)
+            }),
+            map(
+                preceded(tag("float="), take_till1(|c| c == ' ')),
+                |placement: &str| ConfigOption::Float(placement.to_string()),
+            ),
+            map(tag("float"), |_| ConfigOption::Float(String::new())),
+        )),
+        alt((
+            map(
+                preceded(tag("frame="), take_till1(|c| c == ' ')),
+                |frame: &str| ConfigOption::Frame(frame.to_string()),
+            ),
+            map(
+                preceded(tag("framesep="), take_till1(|c| c == ' ')),
+                |sep: &str| ConfigOption::FrameSep(sep.to_string()),
+            ),
+            map(preceded(tag("gobble="), nom_u64), |n| {
+                ConfigOption::Gobble(n as usize)
+            }),
+            map(tag("hide_markers"), |_| ConfigOption::HideMarkers),
+            map(
+                preceded(tag("highlight="), take_till1(|c| c == ' ')),
+                |lines: &str| ConfigOption::Highlight(lines.to_string()),
+            ),
+            map(
+                preceded(tag("highlight_diff="), take_till1(|c| c == ' ')),
+                |hash: &str| ConfigOption::HighlightDiff(hash.to_string()),
+            ),
+            map(
+                delimited(
+                    tag("highlight_regex=\""),
+                    take_till(|c| c == '"'),
+                    char('"'),
+                ),
+                |pattern: &str| ConfigOption::HighlightRegex(pattern.to_string()),
+            ),
+            map(
+                preceded(tag("highlight_rel="), take_till1(|c| c == ' ')),
+                |lines: &str| ConfigOption::HighlightRel(lines.to_string()),
+            ),
+            map(tag("info_as_caption"), |_| ConfigOption::InfoAsCaption),
+            map(tag("info_position=trailing"), |_| {
+                ConfigOption::InfoPosition(InfoPosition::Trailing)
+            }),
+            map(tag("keep_copyright_blank"), |_| {
+                ConfigOption::KeepCopyrightBlank
+            }),
+            map(tag("keep_copyright_comment"), |_| {
+                ConfigOption::KeepCopyrightComment
+            }),
+            map(tag("keep_shebang"), |_| ConfigOption::KeepShebang),
+            map(
+                preceded(tag("langs="), take_till1(|c| c == ' ')),
+                |langs: &str| ConfigOption::Langs(langs.to_string()),
+            ),
+            map(
+                preceded(tag("language="), take_till1(|c| c == ' ')),
+                |language: &str| ConfigOption::Language(language.to_string()),
+            ),
+            map(
+                preceded(tag("linenumcolor="), take_till1(|c| c == ' ')),
+                |color: &str| ConfigOption::LineNumColor(color.to_string()),
+            ),
+            map(
+                preceded(tag("marker="), take_till1(|c| c == ' ')),
+                |name: &str| ConfigOption::Marker(name.to_string()),
+            ),
+            map(tag("no_separator"), |_| ConfigOption::NoSeparator),
+            map(tag("noinfo"), |_| ConfigOption::NoInfo),
+            map(tag("noscopes"), |_| ConfigOption::NoScopes),
+            map(
+                preceded(tag("numbersep="), take_till1(|c| c == ' ')),
+                |sep: &str| ConfigOption::NumberSep(sep.to_string()),
+            ),
+        )),
+        alt((
+            map(tag("renumber"), |_| ConfigOption::Renumber),
+            map(
+                delimited(tag("scope_header=\""), take_till(|c| c == '"'), char('"')),
+                |header: &str| ConfigOption::ScopeHeader(header.to_string()),
+            ),
+            map(tag("show_markers"), |_| ConfigOption::ShowMarkers),
+            map(preceded(tag("stepnumber="), nom_u64), |n| {
+                ConfigOption::StepNumber(n as usize)
+            }),
+            map(tag("trim_blank_body_edges"), |_| {
+                ConfigOption::TrimBlankBodyEdges
+            }),
+        )),
+    ))(input)
+}
+
+/// Check that a ``frame=`` value is one of the frame styles minted understands.
+fn validate_frame(frame: &str) -> Result<()> {
+    match frame {
+        "none" | "leftline" | "topline" | "bottomline" | "lines" | "single" => Ok(()),
+        _ => Err(eyre!(
+            "Unknown frame style {frame:?}; minted understands none, leftline, topline, \
+             bottomline, lines, and single"
+        )),
+    }
+}
+
+/// Parse a space-separated list of config options.
+fn parse_config_options(input: &str) -> IResult<&str, Vec<ConfigOption>> {
+    separated_list0(char(' '), config_option)(input)
+}
+
+/// The structure of an inline config table on a third ``%:`` line of a snippet comment.
+///
+/// Every field is optional, so the table only overrides the options it actually mentions.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InlineConfig {
+    /// The macros to apply, by name with the trailing ``!``.
+    macros: Option<Vec<String>>,
+
+    /// See [`Config::allow_long`].
+    allow_long: Option<bool>,
+
+    /// See [`Config::autogobble`].
+    autogobble: Option<bool>,
+
+    /// See [`Config::backend`]. The only recognized values are ``minted`` and ``verbatim``.
+    backend: Option<String>,
+
+    /// See [`Config::blame`].
+    blame: Option<bool>,
+
+    /// See [`Config::breakanywhere`].
+    breakanywhere: Option<bool>,
+
+    /// See [`Config::breaklines`].
+    breaklines: Option<bool>,
+
+    /// See [`Config::caption`]. The special string ``commit`` means the commit summary.
+    caption: Option<String>,
+
+    /// The info comment syntax, as a template like ``// {}``.
+    comment: Option<String>,
+
+    /// See [`Config::compact_scopes`].
+    compact_scopes: Option<bool>,
+
+    /// See [`Config::context`].
+    context: Option<usize>,
+
+    /// See [`Config::dedent`].
+    dedent: Option<bool>,
+
+    /// See [`Config::diff_hash`].
+    diff: Option<String>,
+
+    /// See [`Config::elide`].
+    elide: Option<String>,
+
+    /// See [`Config::exclude`].
+    exclude: Option<String>,
+
+    /// See [`Config::ellipsis`].
+    ellipsis: Option<String>,
+
+    /// See [`Config::expand_to_scope`].
+    expand_to_scope: Option<bool>,
+
+    /// See [`Config::firstnumber`].
+    firstnumber: Option<usize>,
+
+    /// See [`Config::float`]. An empty string means a float without a placement specifier.
+    float: Option<String>,
+
+    /// See [`Config::frame`]. The only recognized values are minted's frame styles.
+    frame: Option<String>,
+
+    /// See [`Config::framesep`].
+    framesep: Option<String>,
+
+    /// See [`Config::gobble`].
+    gobble: Option<usize>,
+
+    /// See [`Config::highlight_lines`].
+    highlight: Option<String>,
+
+    /// See [`Config::highlight_diff`].
+    highlight_diff: Option<String>,
+
+    /// See [`Config::highlight_regex`].
+    highlight_regex: Option<String>,
+
+    /// See [`Config::highlight_lines_relative`].
+    highlight_rel: Option<String>,
+
+    /// See [`Config::info_as_caption`].
+    info_as_caption: Option<bool>,
+
+    /// See [`Config::info_position`]. The only recognized values are ``leading`` and
+    /// ``trailing``.
+    info_position: Option<String>,
+
+    /// See [`Config::keep_copyright_blank`].
+    keep_copyright_blank: Option<bool>,
+
+    /// See [`Config::keep_copyright_comment`].
+    keep_copyright_comment: Option<bool>,
+
+    /// See [`Config::keep_shebang`].
+    keep_shebang: Option<bool>,
+
+    /// See [`Config::langs`].
+    langs: Option<String>,
+
+    /// See [`Config::language`].
+    language: Option<String>,
+
+    /// See [`Config::linenum_color`].
+    linenumcolor: Option<String>,
+
+    /// See [`Config::marker`].
+    marker: Option<String>,
+
+    /// See [`Config::no_separator`].
+    no_separator: Option<bool>,
+
+    /// See [`Config::noinfo`].
+    noinfo: Option<bool>,
+
+    /// See [`Config::noscopes`].
+    noscopes: Option<bool>,
+
+    /// See [`Config::numbersep`].
+    numbersep: Option<String>,
+
+    /// See [`Config::scope_header`].
+    scope_header: Option<String>,
+
+    /// See [`Config::show_markers`].
+    show_markers: Option<bool>,
+
+    /// See [`Config::stepnumber`].
+    stepnumber: Option<usize>,
+
+    /// See [`Config::trim_blank_body_edges`].
+    trim_blank_body_edges: Option<bool>,
+}
+
+/// A helper to give the inline config table a top-level key, which the TOML crate requires.
+#[derive(Debug, Deserialize)]
+struct InlineConfigWrapper {
+    /// The table itself.
+    config: InlineConfig,
+}
+
+/// A struct to hold the configuration options of a single snippet.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Config {
+    /// The macros that were applied to this config, in the order they were written.
+    pub macros: Vec<ConfigMacro>,
+
+    /// The syntax used to wrap the info comment lines.
+    pub info_comment_syntax: InfoCommentSyntax,
+
+    /// Whether the provenance info should become the minted caption instead of comment
+    /// lines inside the listing.
+    pub info_as_caption: bool,
+
+    /// Where the provenance info comment goes: leading header lines, or trailing on the
+    /// first body line.
+    pub info_position: InfoPosition,
+
+    /// How many lines of context to include around each line range, clamped to the file.
+    pub context: usize,
+
+    /// Whether this snippet may exceed the ``--max-lines`` limit without a warning.
+    pub allow_long: bool,
+
+    /// Whether to pass minted's ``autogobble`` option, stripping the common whitespace prefix
+    /// at render time while keeping the real file line numbers.
+    pub autogobble: bool,
+
+    /// The LaTeX environment to render with.
+    pub backend: Backend,
+
+    /// Whether to list the commits that last touched the snippet lines in the info comment.
+    pub blame: bool,
+
+    /// Whether to let minted break long lines at any character, not just at spaces.
+    pub breakanywhere: bool,
+
+    /// Whether to let minted break long lines instead of letting them overflow the page.
+    pub breaklines: bool,
+
+    /// The caption to show below the snippet, if any.
+    pub caption: Option<Caption>,
+
+    /// Whether to collapse the scope headers onto a single ``... ``-joined line above the body.
+    pub compact_scopes: bool,
+
+    /// Whether to strip the common indentation from the body and scope lines.
+    pub dedent: bool,
+
+    /// The hash of another commit to diff the file against, if any.
+    pub diff_hash: Option<String>,
+
+    /// A range like ``25-35`` whose lines are elided from the middle of the displayed ranges,
+    /// if any. The surrounding lines render as two bodies with the usual ``...`` gap between.
+    pub elide: Option<String>,
+
+    /// Comma-separated absolute line numbers to drop from inside the displayed ranges, each
+    /// leaving a ``...`` gap, if any.
+    pub exclude: Option<String>,
+
+    /// The gap indicator text shown in place of a line number between chunks, or `None` for the
+    /// default ``... ``.
+    pub ellipsis: Option<String>,
+
+    /// Whether to grow each line range to the whole enclosing ``def``/``class`` block.
+    pub expand_to_scope: bool,
+
+    /// The number the first body line should display instead of its real line number, if
+    /// any. Every other displayed number keeps its relative offset.
+    pub firstnumber: Option<usize>,
+
+    /// The placement specifier of a ``listing`` float to wrap the snippet in, if any. An empty
+    /// string floats with LaTeX's default placement.
+    pub float: Option<String>,
+
+    /// The minted ``frame`` style drawn around the listing, if any.
+    pub frame: Option<String>,
+
+    /// The minted ``framesep`` dimension between the frame and the code, if any.
+    pub framesep: Option<String>,
+
+    /// How many characters minted should gobble from the start of each line, if any.
+    pub gobble: Option<usize>,
+
+    /// The lines to pass to minted's ``highlightlines`` option, if any.
+    pub highlight_lines: Option<String>,
+
+    /// The hash of an older commit whose changes are highlighted, if any. The lines of the
+    /// snippet that differ from the file at that commit are merged into `highlight_lines` in
+    /// [`Comment::get_text`](crate::comment::Comment::get_text). The special value ``prev``
+    /// is resolved by the document processor to the previous snippet of the same file.
+    pub highlight_diff: Option<String>,
+
+    /// A regex whose matching body lines are highlighted, if any. The matches are found in
+    /// [`Comment::get_text`](crate::comment::Comment::get_text) and merged into
+    /// `highlight_lines`.
+    pub highlight_regex: Option<String>,
+
+    /// Highlight ranges counted within the displayed snippet body, if any. These are translated
+    /// to absolute line numbers in [`Comment::get_text`](crate::comment::Comment::get_text) and
+    /// merged into `highlight_lines`.
+    pub highlight_lines_relative: Option<String>,
+
+    /// Whether to keep the blank line that follows a stripped copyright comment.
+    pub keep_copyright_blank: bool,
+
+    /// Whether to keep the copyright comment in a whole-file snippet.
+    pub keep_copyright_comment: bool,
+
+    /// Whether to keep a leading ``#!`` line in a whole-file snippet whose copyright comment
+    /// is stripped, shown as its own line 1 with a gap down to the code.
+    pub keep_shebang: bool,
+
+    /// Comma-separated languages aligned to the line ranges, if any. Each body then renders
+    /// as its own ``minted`` environment with the matching lexer.
+    pub langs: Option<String>,
+
+    /// The language to use for the ``minted`` environment, or `None` to infer it from the
+    /// snippet's file extension.
+    pub language: Option<String>,
+
+    /// The color of the line-number gutter, if any: either an ``rgb`` triple like ``0.5,0.5,1``
+    /// or a named color like ``gray``. `None` means the usual light blue.
+    pub linenum_color: Option<String>,
+
+    /// The name of a ``SNIPPET-START``/``SNIPPET-END`` marker pair selecting the lines to
+    /// include, if any. Markers take precedence over line ranges.
+    pub marker: Option<String>,
+
+    /// Whether to drop the blank line between the info comments and the body.
+    pub no_separator: bool,
+
+    /// Whether to omit the info comment lines above the snippet entirely.
+    pub noinfo: bool,
+
+    /// Whether to suppress the scope lines above the snippet body.
+    pub noscopes: bool,
+
+    /// The minted ``numbersep`` dimension between the line numbers and the code, if any.
+    pub numbersep: Option<String>,
+
+    /// Whether to display sequential line numbers starting from 1, ignoring the real file line
+    /// numbers, while still compressing gaps with ``...``.
+    pub renumber: bool,
+
+    /// A literal scope header line to show above the body in place of the detected scopes, if
+    /// any. This works with ``noscopes``, which only disables the automatic detection.
+    pub scope_header: Option<String>,
+
+    /// Whether to include the marker lines of a ``marker=`` range instead of hiding them.
+    pub show_markers: bool,
+
+    /// Show a line number only on every Nth (displayed) line, if set, matching journal
+    /// styles that number sparsely.
+    pub stepnumber: Option<usize>,
+
+    /// Whether to drop blank lines from the start and end of each body, keeping the reported
+    /// line numbers accurate to the remaining content.
+    pub trim_blank_body_edges: bool,
+}
+
+impl Config {
+    /// Parse a config from the options at the end of a snippet comment, on top of the
+    /// project-wide defaults (if any were loaded with [`load_project_config`]).
+    pub fn parse(text: &str) -> Result<Self> {
+        Self::parse_with_base(text, DEFAULT_CONFIG.get().cloned().unwrap_or_default())
+    }
+
+    /// Parse a config from the options at the end of a snippet comment, layering them on top
+    /// of the given base config. Options the comment doesn't mention keep the base's values.
+    pub fn parse_with_base(text: &str, base: Self) -> Result<Self> {
+        let (rest, options) = parse_config_options(text.trim())
+            .map_err(|e| eyre!("Failed to parse config options {text:?}: {e}"))?;
+        if !rest.is_empty() {
+            return Err(eyre!("Trailing text {rest:?} in config options {text:?}"));
+        }
+
+        let mut config = base;
+        for option in options {
+            match option {
+                ConfigOption::Macro(config_macro) => {
+                    config_macro.apply(&mut config);
+                    config.macros.push(config_macro);
+                }
+                ConfigOption::AllowLong => config.allow_long = true,
+                ConfigOption::Autogobble => config.autogobble = true,
+                ConfigOption::Backend(backend) => config.backend = backend,
+                ConfigOption::Blame => config.blame = true,
+                ConfigOption::BreakAnywhere => config.breakanywhere = true,
+                ConfigOption::BreakLines => config.breaklines = true,
+                ConfigOption::Caption(caption) => config.caption = Some(caption),
+                ConfigOption::Comment(syntax) => {
+                    config.info_comment_syntax = InfoCommentSyntax::parse(&syntax)?
+                }
+                ConfigOption::CompactScopes => config.compact_scopes = true,
+                ConfigOption::Context(n) => config.context = n,
+                ConfigOption::Dedent => config.dedent = true,
+                ConfigOption::Diff(hash) => config.diff_hash = Some(hash),
+                ConfigOption::Elide(range) => config.elide = Some(range),
+                ConfigOption::Exclude(lines) => config.exclude = Some(lines),
+                ConfigOption::Ellipsis(ellipsis) => config.ellipsis = Some(ellipsis),
+                ConfigOption::ExpandToScope => config.expand_to_scope = true,
+                ConfigOption::FirstNumber(n) => config.firstnumber = Some(n),
+                ConfigOption::Float(placement) => config.float = Some(placement),
+                ConfigOption::Frame(frame) => {
+                    validate_frame(&frame)?;
+                    config.frame = Some(frame)
+                }
+                ConfigOption::FrameSep(sep) => config.framesep = Some(sep),
+                ConfigOption::Gobble(n) => config.gobble = Some(n),
+                ConfigOption::Highlight(lines) => config.highlight_lines = Some(lines),
+                ConfigOption::HighlightDiff(hash) => config.highlight_diff = Some(hash),
+                ConfigOption::HighlightRegex(pattern) => config.highlight_regex = Some(pattern),
+                ConfigOption::HighlightRel(lines) => config.highlight_lines_relative = Some(lines),
+                ConfigOption::InfoAsCaption => config.info_as_caption = true,
+                ConfigOption::InfoPosition(position) => config.info_position = position,
+                ConfigOption::KeepCopyrightBlank => config.keep_copyright_blank = true,
+                ConfigOption::KeepCopyrightComment => config.keep_copyright_comment = true,
+                ConfigOption::KeepShebang => config.keep_shebang = true,
+                ConfigOption::Langs(langs) => config.langs = Some(langs),
+                ConfigOption::Language(language) => config.language = Some(language),
+                ConfigOption::LineNumColor(color) => config.linenum_color = Some(color),
+                ConfigOption::Marker(name) => config.marker = Some(name),
+                ConfigOption::NoSeparator => config.no_separator = true,
+                ConfigOption::NoInfo => config.noinfo = true,
+                ConfigOption::NoScopes => config.noscopes = true,
+                ConfigOption::NumberSep(sep) => config.numbersep = Some(sep),
+                ConfigOption::Renumber => config.renumber = true,
+                ConfigOption::ScopeHeader(header) => config.scope_header = Some(header),
+                ConfigOption::HideMarkers => config.show_markers = false,
+                ConfigOption::ShowMarkers => config.show_markers = true,
+                ConfigOption::StepNumber(n) => config.stepnumber = Some(n),
+                ConfigOption::TrimBlankBodyEdges => config.trim_blank_body_edges = true,
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Apply an inline TOML table from a third ``%:`` line on top of this config.
+    ///
+    /// The table only overrides the options it mentions, so it can be freely combined with the
+    /// flat options on the filename line.
+    pub fn apply_inline(&mut self, text: &str) -> Result<()> {
+        let InlineConfigWrapper { config: inline } = toml::from_str(&format!("config = {text}"))
+            .map_err(|e| eyre!("Failed to parse inline config {text:?}: {e}"))?;
+
+        if let Some(macros) = inline.macros {
+            for name in macros {
+                let config_macro = ConfigMacro::parse(&name)
+                    .ok_or_else(|| eyre!("Unknown macro {name:?} in inline config"))?;
+                config_macro.apply(self);
+                self.macros.push(config_macro);
+            }
+        }
+        if let Some(allow_long) = inline.allow_long {
+            self.allow_long = allow_long;
+        }
+        if let Some(autogobble) = inline.autogobble {
+            self.autogobble = autogobble;
+        }
+        if let Some(backend) = inline.backend {
+            self.backend = match backend.as_str() {
+                "minted" => Backend::Minted,
+                "verbatim" => Backend::Verbatim,
+                _ => return Err(eyre!("Unknown backend {backend:?} in inline config")),
+            };
+        }
+        if let Some(blame) = inline.blame {
+            self.blame = blame;
+        }
+        if let Some(breakanywhere) = inline.breakanywhere {
+            self.breakanywhere = breakanywhere;
+        }
+        if let Some(breaklines) = inline.breaklines {
+            self.breaklines = breaklines;
+        }
+        if let Some(caption) = inline.caption {
+            self.caption = Some(match caption.as_str() {
+                "commit" => Caption::Commit,
+                _ => Caption::Literal(caption),
+            });
+        }
+        if let Some(comment) = inline.comment {
+            self.info_comment_syntax = InfoCommentSyntax::parse(&comment)?;
+        }
+        if let Some(compact_scopes) = inline.compact_scopes {
+            self.compact_scopes = compact_scopes;
+        }
+        if let Some(context) = inline.context {
+            self.context = context;
+        }
+        if let Some(dedent) = inline.dedent {
+            self.dedent = dedent;
+        }
+        if let Some(diff) = inline.diff {
+            self.diff_hash = Some(diff);
+        }
+        if let Some(elide) = inline.elide {
+            self.elide = Some(elide);
+        }
+        if let Some(exclude) = inline.exclude {
+            self.exclude = Some(exclude);
+        }
+        if let Some(ellipsis) = inline.ellipsis {
+            self.ellipsis = Some(ellipsis);
+        }
+        if let Some(expand_to_scope) = inline.expand_to_scope {
+            self.expand_to_scope = expand_to_scope;
+        }
+        if let Some(firstnumber) = inline.firstnumber {
+            self.firstnumber = Some(firstnumber);
+        }
+        if let Some(float) = inline.float {
+            self.float = Some(float);
+        }
+        if let Some(frame) = inline.frame {
+            validate_frame(&frame)?;
+            self.frame = Some(frame);
+        }
+        if let Some(framesep) = inline.framesep {
+            self.framesep = Some(framesep);
+        }
+        if let Some(gobble) = inline.gobble {
+            self.gobble = Some(gobble);
+        }
+        if let Some(highlight) = inline.highlight {
+            self.highlight_lines = Some(highlight);
+        }
+        if let Some(highlight_diff) = inline.highlight_diff {
+            self.highlight_diff = Some(highlight_diff);
+        }
+        if let Some(highlight_regex) = inline.highlight_regex {
+            self.highlight_regex = Some(highlight_regex);
+        }
+        if let Some(highlight_rel) = inline.highlight_rel {
+            self.highlight_lines_relative = Some(highlight_rel);
+        }
+        if let Some(info_as_caption) = inline.info_as_caption {
+            self.info_as_caption = info_as_caption;
+        }
+        if let Some(info_position) = inline.info_position {
+            self.info_position = match info_position.as_str() {
+                "leading" => InfoPosition::Leading,
+                "trailing" => InfoPosition::Trailing,
+                _ => return Err(eyre!("Unknown info_position {info_position:?} in inline config")),
+            };
+        }
+        if let Some(keep_copyright_blank) = inline.keep_copyright_blank {
+            self.keep_copyright_blank = keep_copyright_blank;
+        }
+        if let Some(keep_copyright_comment) = inline.keep_copyright_comment {
+            self.keep_copyright_comment = keep_copyright_comment;
+        }
+        if let Some(keep_shebang) = inline.keep_shebang {
+            self.keep_shebang = keep_shebang;
+        }
+        if let Some(langs) = inline.langs {
+            self.langs = Some(langs);
+        }
+        if let Some(language) = inline.language {
+            self.language = Some(language);
+        }
+        if let Some(linenumcolor) = inline.linenumcolor {
+            self.linenum_color = Some(linenumcolor);
+        }
+        if let Some(marker) = inline.marker {
+            self.marker = Some(marker);
+        }
+        if let Some(no_separator) = inline.no_separator {
+            self.no_separator = no_separator;
+        }
+        if let Some(noinfo) = inline.noinfo {
+            self.noinfo = noinfo;
+        }
+        if let Some(noscopes) = inline.noscopes {
+            self.noscopes = noscopes;
+        }
+        if let Some(numbersep) = inline.numbersep {
+            self.numbersep = Some(numbersep);
+        }
+        if let Some(scope_header) = inline.scope_header {
+            self.scope_header = Some(scope_header);
+        }
+        if let Some(show_markers) = inline.show_markers {
+            self.show_markers = show_markers;
+        }
+        if let Some(stepnumber) = inline.stepnumber {
+            self.stepnumber = Some(stepnumber);
+        }
+        if let Some(trim_blank_body_edges) = inline.trim_blank_body_edges {
+            self.trim_blank_body_edges = trim_blank_body_edges;
+        }
+
+        Ok(())
+    }
+
+    /// Return the non-default options of this config in the canonical comment syntax.
+    ///
+    /// Feeding the result back through [`Config::parse`] yields an equal config, so it can be
+    /// used to regenerate the options of a snippet comment.
+    pub fn details(&self) -> String {
+        // Options already implied by the macros are not repeated
+        let mut base = Self::default();
+        for config_macro in &self.macros {
+            config_macro.apply(&mut base);
+        }
+
+        let mut options: Vec<String> = self.macros.iter().map(ConfigMacro::name).collect();
+
+        if self.allow_long != base.allow_long {
+            options.push(String::from("allow_long"));
+        }
+        if self.autogobble != base.autogobble {
+            options.push(String::from("autogobble"));
+        }
+        if self.backend != base.backend {
+            options.push(String::from("backend=verbatim"));
+        }
+        if self.blame != base.blame {
+            options.push(String::from("blame"));
+        }
+        if self.breakanywhere != base.breakanywhere {
+            options.push(String::from("breakanywhere"));
+        }
+        if self.breaklines != base.breaklines {
+            options.push(String::from("breaklines"));
+        }
+        match &self.caption {
+            Some(Caption::Commit) => options.push(String::from("caption=commit")),
+            Some(Caption::Literal(caption)) => options.push(format!("caption=\"{caption}\"")),
+            None => {}
+        }
+        if self.info_comment_syntax != base.info_comment_syntax {
+            options.push(format!(
+                "comment=\"{}{{}}{}\"",
+                self.info_comment_syntax.before, self.info_comment_syntax.after
+            ));
+        }
+        if self.compact_scopes != base.compact_scopes {
+            options.push(String::from("compact_scopes"));
+        }
+        if self.context != base.context {
+            options.push(format!("context={}", self.context));
+        }
+        if self.dedent != base.dedent {
+            options.push(String::from("dedent"));
+        }
+        if let Some(diff_hash) = &self.diff_hash {
+            options.push(format!("diff={diff_hash}"));
+        }
+        if let Some(elide) = &self.elide {
+            options.push(format!("elide={elide}"));
+        }
+        if let Some(ellipsis) = &self.ellipsis {
+            options.push(format!("ellipsis=\"{ellipsis}\""));
+        }
+        if let Some(exclude) = &self.exclude {
+            options.push(format!("exclude={exclude}"));
+        }
+        if self.expand_to_scope != base.expand_to_scope {
+            options.push(String::from("expand_to_scope"));
+        }
+        if let Some(firstnumber) = self.firstnumber {
+            options.push(format!("firstnumber={firstnumber}"));
+        }
+        match &self.float {
+            Some(placement) if placement.is_empty() => options.push(String::from("float")),
+            Some(placement) => options.push(format!("float={placement}")),
+            None => {}
+        }
+        if let Some(frame) = &self.frame {
+            options.push(format!("frame={frame}"));
+        }
+        if let Some(framesep) = &self.framesep {
+            options.push(format!("framesep={framesep}"));
+        }
+        if let Some(gobble) = self.gobble {
+            options.push(format!("gobble={gobble}"));
+        }
+        if let Some(highlight_lines) = &self.highlight_lines {
+            options.push(format!("highlight={highlight_lines}"));
+        }
+        if let Some(highlight_diff) = &self.highlight_diff {
+            options.push(format!("highlight_diff={highlight_diff}"));
+        }
+        if let Some(highlight_regex) = &self.highlight_regex {
+            options.push(format!("highlight_regex=\"{highlight_regex}\""));
+        }
+        if let Some(highlight_lines_relative) = &self.highlight_lines_relative {
+            options.push(format!("highlight_rel={highlight_lines_relative}"));
+        }
+        if self.info_as_caption != base.info_as_caption {
+            options.push(String::from("info_as_caption"));
+        }
+        if self.info_position != base.info_position {
+            options.push(String::from("info_position=trailing"));
+        }
+        if self.keep_copyright_blank != base.keep_copyright_blank {
+            options.push(String::from("keep_copyright_blank"));
+        }
+        if self.keep_copyright_comment != base.keep_copyright_comment {
+            options.push(String::from("keep_copyright_comment"));
+        }
+        if self.keep_shebang != base.keep_shebang {
+            options.push(String::from("keep_shebang"));
+        }
+        if let Some(langs) = &self.langs {
+            options.push(format!("langs={langs}"));
+        }
+        if self.language != base.language {
+            if let Some(language) = &self.language {
+                options.push(format!("language={language}"));
+            }
+        }
+        if let Some(linenum_color) = &self.linenum_color {
+            options.push(format!("linenumcolor={linenum_color}"));
+        }
+        if let Some(marker) = &self.marker {
+            options.push(format!("marker={marker}"));
+        }
+        if self.no_separator != base.no_separator {
+            options.push(String::from("no_separator"));
+        }
+        if self.noinfo != base.noinfo {
+            options.push(String::from("noinfo"));
+        }
+        if self.noscopes != base.noscopes {
+            options.push(String::from("noscopes"));
+        }
+        if let Some(numbersep) = &self.numbersep {
+            options.push(format!("numbersep={numbersep}"));
+        }
+        if self.renumber != base.renumber {
+            options.push(String::from("renumber"));
+        }
+        if let Some(scope_header) = &self.scope_header {
+            options.push(format!("scope_header=\"{scope_header}\""));
+        }
+        if self.show_markers != base.show_markers {
+            options.push(String::from("show_markers"));
+        }
+        if let Some(stepnumber) = self.stepnumber {
+            options.push(format!("stepnumber={stepnumber}"));
+        }
+        if self.trim_blank_body_edges != base.trim_blank_body_edges {
+            options.push(String::from("trim_blank_body_edges"));
+        }
+
+        options.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_comment_syntax_test() {
+        // A template without exactly one {} used to panic on the regex captures; now it's a
+        // proper error naming the bad value
+        let error = Config::parse(r#"comment="// " noscopes"#).unwrap_err();
+        assert!(error.to_string().contains("must contain exactly one {}"));
+        assert!(error.to_string().contains("// "));
+
+        assert!(Config::parse(r#"comment="{} and {}" noscopes"#).is_err());
+    }
+
+    #[test]
+    fn config_parse_test() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+
+        assert_eq!(
+            Config::parse(r#"comment="// {}" highlight=232-233 language=rust noscopes"#).unwrap(),
+            Config {
+                macros: vec![],
+                info_comment_syntax: InfoCommentSyntax {
+                    before: String::from("// "),
+                    after: String::new(),
+                },
+                allow_long: false,
+                autogobble: false,
+                backend: Backend::Minted,
+                info_as_caption: false,
+                info_position: InfoPosition::Leading,
+                blame: false,
+                breakanywhere: false,
+                breaklines: false,
+                caption: None,
+                compact_scopes: false,
+                context: 0,
+                dedent: false,
+                diff_hash: None,
+                elide: None,
+                exclude: None,
+                ellipsis: None,
+                expand_to_scope: false,
+                float: None,
+                firstnumber: None,
+                frame: None,
+                framesep: None,
+                gobble: None,
+                stepnumber: None,
+                highlight_lines: Some(String::from("232-233")),
+                highlight_diff: None,
+                highlight_regex: None,
+                highlight_lines_relative: None,
+                keep_copyright_blank: false,
+                keep_copyright_comment: false,
+                keep_shebang: false,
+                langs: None,
+                language: Some(String::from("rust")),
+                linenum_color: None,
+                marker: None,
+                no_separator: false,
+                noinfo: false,
+                noscopes: true,
+                numbersep: None,
+                renumber: false,
+                scope_header: None,
+                show_markers: false,
+                trim_blank_body_edges: false,
+            }
+        );
+
+        assert_eq!(
+            Config::parse("keep_copyright_comment").unwrap(),
+            Config {
+                keep_copyright_comment: true,
+                keep_shebang: false,
+                ..Config::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_with_base_test() {
+        // Options the comment sets override the base; the rest carry through
+        let base = Config::parse("linenumcolor=gray noscopes").unwrap();
+        let config = Config::parse_with_base("dedent linenumcolor=0.8,0.2,0.2", base).unwrap();
+
+        assert_eq!(config.linenum_color.as_deref(), Some("0.8,0.2,0.2"));
+        assert!(config.noscopes);
+        assert!(config.dedent);
+    }
+
+    #[test]
+    fn option_order_test() {
+        let expected =
+            Config::parse("highlight=10 keep_copyright_comment language=rust noscopes").unwrap();
+
+        for options in [
+            "noscopes language=rust keep_copyright_comment highlight=10",
+            "language=rust highlight=10 noscopes keep_copyright_comment",
+            "keep_copyright_comment noscopes highlight=10 language=rust",
+            "noscopes keep_copyright_comment language=rust highlight=10",
+        ] {
+            assert_eq!(Config::parse(options).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn details_round_trip_test() {
+        let configs = [
+            "",
+"allow_long noscopes",
+            "autogobble",
+            "dedent highlight=232-233",
+            r#"comment="// {}" language=rust"#,
+            "keep_copyright_comment noscopes",
+            "markdown!",
+            "autogobble dedent highlight=45 keep_copyright_comment language=yaml noscopes",
+            "highlight_rel=2-3,5 noinfo",
+            "blame trim_blank_body_edges",
+            "renumber",
+            r#"highlight_regex="self\._matrices" noscopes"#,
+            "context=2 dedent",
+            r#"ellipsis="[...] " noinfo"#,
+            "gobble=4 noscopes",
+            "breakanywhere breaklines",
+            "caption=commit noinfo",
+            r#"caption="A caption" noscopes"#,
+            "no_separator",
+            "marker=parser noinfo",
+            "marker=parser noinfo show_markers",
+            "expand_to_scope noscopes",
+            r#"noscopes scope_header="class Foo:""#,
+            "compact_scopes",
+            "backend=verbatim noscopes",
+            "highlight_diff=prev noscopes",
+            "elide=25-35 noscopes",
+            "linenumcolor=gray noscopes",
+            "linenumcolor=0.8,0.2,0.2 noscopes",
+            "caption=commit float noscopes",
+            "caption=commit float=htbp noscopes",
+            "langs=python,sql noinfo noscopes",
+            "keep_shebang noscopes",
+            "info_as_caption noscopes",
+            "info_position=trailing noscopes",
+            "stepnumber=5 noscopes",
+            "exclude=15,22 noscopes",
+            "noscopes numbersep=12pt",
+            "frame=single framesep=2mm noscopes",
+            "firstnumber=1 noscopes",
+        ]
+        .map(|options| Config::parse(options).unwrap());
+
+        for config in configs {
+            assert_eq!(Config::parse(&config.details()).unwrap(), config);
+        }
+    }
+
+    #[test]
+    fn repo_url_test() {
+        // The default must be checked before the setter runs, since setting is process-global
+        assert_eq!(repo_url(), "https://github.com/DoctorDalek1963/lintrans");
+
+        set_repo_url("https://example.com/mirror/lintrans/");
+        assert_eq!(repo_url(), "https://example.com/mirror/lintrans");
+    }
+
+    #[test]
+    fn config_macro_parse_test() {
+        assert_eq!(ConfigMacro::parse("markdown!"), Some(ConfigMacro::Markdown));
+        assert_eq!(ConfigMacro::parse("rust!"), Some(ConfigMacro::Rust));
+        assert_eq!(ConfigMacro::parse("c!"), Some(ConfigMacro::C));
+        assert_eq!(ConfigMacro::parse("cpp!"), Some(ConfigMacro::Cpp));
+        assert_eq!(ConfigMacro::parse("nonsense!"), None);
+
+        assert_eq!(
+            Config::parse("rust!").unwrap(),
+            Config {
+                macros: vec![ConfigMacro::Rust],
+                info_comment_syntax: InfoCommentSyntax {
+                    before: String::from("// "),
+                    after: String::new(),
+                },
+                language: Some(String::from("rust")),
+                ..Config::default()
+            }
+        );
+
+        assert_eq!(
+            Config::parse("markdown!").unwrap(),
+            Config {
+                macros: vec![ConfigMacro::Markdown],
+                info_comment_syntax: InfoCommentSyntax {
+                    before: String::from("<!-- "),
+                    after: String::from(" -->"),
+                },
+                language: Some(String::from("markdown")),
+                ..Config::default()
+            }
+        );
+
+        // The macro is remembered, so details() can re-emit it by name
+        assert_eq!(Config::parse("markdown!").unwrap().details(), "markdown!");
+    }
+
+    #[test]
+    fn inline_config_test() {
+        let mut config = Config::parse("dedent").unwrap();
+        config
+            .apply_inline(r#"{ language = "rust", highlight = "3-5", noscopes = true }"#)
+            .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                dedent: true,
+                highlight_lines: Some(String::from("3-5")),
+                language: Some(String::from("rust")),
+                noscopes: true,
+                numbersep: None,
+                ..Config::default()
+            }
+        );
+
+        // The inline form can also apply macros by name
+        let mut config = Config::default();
+        config
+            .apply_inline(r#"{ macros = ["markdown!"] }"#)
+            .unwrap();
+        assert_eq!(config.language.as_deref(), Some("markdown"));
+
+        assert!(Config::default().apply_inline("{ nonsense = 1 }").is_err());
+    }
+
+    #[test]
+    fn custom_macro_test() {
+        load_project_config(concat!(
+            "[macros.tex]\n",
+            "language = \"tex\"\n",
+            "comment = \"% {}\"\n",
+            "\n",
+            "[macros.rust]\n",
+            "language = \"not-rust\"\n",
+        ))
+        .unwrap();
+
+        let config = Config::parse("tex!").unwrap();
+        assert_eq!(config.language.as_deref(), Some("tex"));
+        assert_eq!(
+            config.info_comment_syntax,
+            InfoCommentSyntax {
+                before: String::from("% "),
+                after: String::new(),
+            }
+        );
+        assert_eq!(config.details(), "tex!");
+
+        // A built-in macro wins over a custom macro with the same name
+        assert_eq!(
+            Config::parse("rust!").unwrap().language.as_deref(),
+            Some("rust")
+        );
+    }
+}
@@ -0,0 +1,116 @@
+// lintrans - The linear transformation visualizer
+// Copyright (C) 2021-2022 D. Dyson (DoctorDalek1963)
+
+// This program is licensed under GNU GPLv3, available here:
+// <https://www.gnu.org/licenses/gpl-3.0.html>
+
+//! This module provides a bundled list of Pygments lexer aliases, used by ``--check-languages``
+//! to catch typos like ``language=pyton`` at processing time rather than when LaTeX runs.
+
+/// The Pygments lexer aliases that minted is known to accept.
+///
+/// This is not the full Pygments list, just the aliases that could plausibly appear in the
+/// write-up; an unknown language only warns, so missing entries are a papercut, not a failure.
+static KNOWN_LANGUAGES: &[&str] = &[
+    "asm",
+    "awk",
+    "bash",
+    "bat",
+    "c",
+    "c++",
+    "clojure",
+    "cmake",
+    "console",
+    "cpp",
+    "css",
+    "cuda",
+    "dart",
+    "diff",
+    "docker",
+    "dockerfile",
+    "elixir",
+    "erlang",
+    "fortran",
+    "fsharp",
+    "go",
+    "golang",
+    "graphql",
+    "haskell",
+    "hs",
+    "html",
+    "ini",
+    "java",
+    "javascript",
+    "js",
+    "json",
+    "kotlin",
+    "latex",
+    "lisp",
+    "lua",
+    "make",
+    "makefile",
+    "markdown",
+    "matlab",
+    "md",
+    "nasm",
+    "nginx",
+    "ocaml",
+    "octave",
+    "pascal",
+    "perl",
+    "php",
+    "powershell",
+    "proto",
+    "protobuf",
+    "ps1",
+    "py",
+    "python",
+    "python3",
+    "r",
+    "rb",
+    "rs",
+    "ruby",
+    "rust",
+    "scala",
+    "scheme",
+    "sed",
+    "sh",
+    "shell",
+    "sql",
+    "swift",
+    "tex",
+    "text",
+    "toml",
+    "ts",
+    "typescript",
+    "udiff",
+    "verilog",
+    "vhdl",
+    "vim",
+    "xml",
+    "yaml",
+    "zsh",
+];
+
+/// Return whether the given language is a known Pygments lexer alias.
+///
+/// The ``lexers.py:MyLexer -x`` custom-lexer form is always allowed, since it names a lexer
+/// class rather than an alias.
+pub fn is_known(language: &str) -> bool {
+    language.contains(" -x") || KNOWN_LANGUAGES.contains(&language)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_known_test() {
+        assert!(is_known("python"));
+        assert!(is_known("diff"));
+        assert!(is_known("lexers.py:MyLexer -x"));
+
+        assert!(!is_known("pyton"));
+        assert!(!is_known(""));
+    }
+}
@@ -0,0 +1,669 @@
+// lintrans - The linear transformation visualizer
+// Copyright (C) 2021-2022 D. Dyson (DoctorDalek1963)
+
+// This program is licensed under GNU GPLv3, available here:
+// <https://www.gnu.org/licenses/gpl-3.0.html>
+
+//! This module provides full pipeline tests, going from a LaTeX comment to the final LaTeX text.
+
+use git2::Repository;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use crate::comment::Comment;
+use crate::{process_snippets, Verbosity};
+
+/// The hash of the commit that the test snippets are pinned to.
+///
+/// The fixture repo is committed with a fixed signature and timestamp, so this hash is fully
+/// determined by the files under ``tests/fixtures`` and stays stable across machines.
+pub const TEST_HASH: &str = "48a165778427971abce76d260b4fd6cdcd5e2197";
+
+/// The path of the fixture repo, once it's been built.
+static FIXTURE_REPO: OnceLock<PathBuf> = OnceLock::new();
+
+/// Open the fixture repo that the test snippets are taken from.
+///
+/// The repo is built on first use from the files under ``tests/fixtures``, so the tests don't
+/// need a full lintrans checkout to run.
+pub fn get_repo() -> Repository {
+    let path = FIXTURE_REPO.get_or_init(build_fixture_repo);
+    Repository::open(path).expect("The fixture repo should open once it's been built")
+}
+
+/// Build the fixture repo in the temp directory and return its path.
+fn build_fixture_repo() -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "process-code-snippets-fixture-{}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&path);
+
+    let repo = Repository::init(&path).expect("The fixture repo should initialize");
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let files = copy_dir(&fixtures, &path, Path::new(""));
+
+    let mut index = repo.index().expect("The fixture repo should have an index");
+    for file in files {
+        index.add_path(&file).expect("The fixture file should be addable");
+    }
+    index.write().expect("The fixture index should be writable");
+
+    let tree_id = index.write_tree().expect("The fixture tree should be writable");
+    let tree = repo.find_tree(tree_id).expect("The fixture tree should exist");
+
+    // The signature is pinned so that the commit hash always comes out as TEST_HASH
+    let signature =
+        git2::Signature::new("lintrans fixture", "fixture@lintrans", &git2::Time::new(0, 0))
+            .expect("The fixture signature should be valid");
+    let oid = repo
+        .commit(Some("HEAD"), &signature, &signature, "baseline", &tree, &[])
+        .expect("The fixture commit should succeed");
+    assert_eq!(oid.to_string(), TEST_HASH, "The fixture repo hash has drifted");
+
+    // A lightweight tag for the tag-pinning tests; tags don't affect the commit hash
+    let object = repo.find_object(oid, None).expect("The fixture commit should be findable");
+    repo.tag_lightweight("v9.9", &object, true)
+        .expect("The fixture tag should be creatable");
+    drop(object);
+
+    drop(tree);
+    drop(repo);
+    path
+}
+
+/// Recursively copy the fixture files into the repo, returning their relative paths.
+fn copy_dir(source: &Path, destination: &Path, relative: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    for entry in fs::read_dir(source.join(relative)).expect("The fixture dir should be readable") {
+        let entry = entry.expect("The fixture dir entry should be readable");
+        let relative = relative.join(entry.file_name());
+
+        if entry.path().is_dir() {
+            fs::create_dir_all(destination.join(&relative))
+                .expect("The fixture subdir should be creatable");
+            files.extend(copy_dir(source, destination, &relative));
+        } else {
+            fs::copy(entry.path(), destination.join(&relative))
+                .expect("The fixture file should be copyable");
+            files.push(relative);
+        }
+    }
+    files
+}
+
+/// Go from a LaTeX comment to the final LaTeX text in one call.
+pub fn get_latex(latex_comment: &str) -> String {
+    Comment::from_latex_comment(latex_comment)
+        .expect("The LaTeX comment should be parseable")
+        .get_text(&get_repo())
+        .expect("The comment should resolve against the repo")
+        .get_latex()
+}
+
+#[test]
+fn overlapping_comment_replacement_test() {
+    // The first comment's text is a prefix of the second's, which used to make str::replace
+    // corrupt the second comment before it was processed
+    let contents = format!(
+        "Before\n\n%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 noscopes\n\nMiddle\n\n%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56\n\nAfter\n"
+    );
+    let processed = process_snippets(&get_repo(), &contents, Verbosity::Quiet, &mut vec![]).unwrap();
+
+    assert!(!processed.contains("%:"));
+    assert!(processed.starts_with("Before\n") && processed.ends_with("After\n"));
+
+    // Only the second comment shows its enclosing scope
+    let (first_part, second_part) = processed.split_once("Middle").unwrap();
+    assert!(!first_part.contains("class MatrixWrapper:"));
+    assert!(second_part.contains("class MatrixWrapper:"));
+}
+
+#[test]
+fn deterministic_output_test() {
+    // CI diffs the generated PDFs, so processing the same input twice must be byte-identical.
+    // Options are kept in ordered Vecs rather than HashMaps precisely for this reason
+    let contents = format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 dedent highlight=47-48\n\n%: {TEST_HASH}\n%: compile.py language=text noscopes\n"
+    );
+    let repo = get_repo();
+
+    let first_run = process_snippets(&repo, &contents, Verbosity::Quiet, &mut vec![]).unwrap();
+    let second_run = process_snippets(&repo, &contents, Verbosity::Quiet, &mut vec![]).unwrap();
+    assert_eq!(first_run, second_run);
+}
+
+#[test]
+fn simple_range_test() {
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56"
+    ));
+    assert_eq!(
+        latex,
+        format!(
+            r"{{\renewcommand{{\theFancyVerbLine}}{{\textcolor[rgb]{{0.5,0.5,1}}{{\ifnum\value{{FancyVerbLine}}<23\else\ifnum\value{{FancyVerbLine}}<25\arabic{{FancyVerbLine}}\else\ifnum\value{{FancyVerbLine}}=25... \else\the\numexpr\value{{FancyVerbLine}}+19\relax\fi\fi\fi}}}}
+\begin{{minted}}[linenos,firstnumber=21]{{python}}
+# {TEST_HASH}
+# src/lintrans/matrices/wrapper.py
+
+class MatrixWrapper:
+
+    def __init__(self):
+        {}
+        self._matrices: Dict[str, Optional[Union[MatrixType, str]]] = {{
+            'A': None, 'B': None, 'C': None, 'D': None,
+            'E': None, 'F': None, 'G': None, 'H': None,
+            'I': np.eye(2),  # I is always defined as the identity matrix
+            'J': None, 'K': None, 'L': None, 'M': None,
+            'N': None, 'O': None, 'P': None, 'Q': None,
+            'R': None, 'S': None, 'T': None, 'U': None,
+            'V': None, 'W': None, 'X': None, 'Y': None,
+            'Z': None
+        }}
+\end{{minted}}
+}}",
+            r#""""Initialize a :class:`MatrixWrapper` object with a dictionary of matrices which can be accessed.""""#
+        )
+    );
+}
+
+#[test]
+fn autogobble_test() {
+    // The whitespace prefix is stripped by minted at render time, so the text keeps its
+    // indentation here and the line numbers stay the real file line numbers
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 autogobble"
+    ));
+    assert!(latex.contains("\\begin{minted}[linenos,firstnumber=21,autogobble]{python}"));
+    assert!(latex.contains("\n    def __init__(self):\n"));
+}
+
+#[test]
+fn block_info_comment_test() {
+    // Each info comment spans 3 lines, so there are 6 info lines to hide instead of 2, and
+    // firstnumber is offset by -7 instead of -3
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 comment=\"/*\\n * {{}}\\n */\" noscopes"
+    ));
+    assert!(latex.contains("\\begin{minted}[linenos,firstnumber=38]{python}"));
+    assert!(latex.contains("\\ifnum\\value{FancyVerbLine}<44\\else"));
+    assert!(latex.contains(&format!("/*\n * {TEST_HASH}\n */\n")));
+}
+
+#[test]
+fn scope_header_test() {
+    // The literal header shows even though automatic detection is off, and since the line does
+    // exist in the file, it gets its real line number and renders like a detected scope
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 noscopes scope_header=\"class MatrixWrapper:\""
+    ));
+    assert!(latex.contains("\nclass MatrixWrapper:\n"));
+    assert!(latex.contains("\\begin{minted}[linenos,firstnumber=21]{python}"));
+}
+
+#[test]
+fn no_separator_test() {
+    // Only the two info lines are hidden, so firstnumber is offset by -2 instead of -3
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 no_separator noscopes"
+    ));
+    assert!(latex.contains("\\begin{minted}[linenos,firstnumber=43]{python}"));
+    assert!(latex.contains("\\ifnum\\value{FancyVerbLine}<44\\else"));
+    assert!(latex.contains("wrapper.py\n    def __init__(self):\n"));
+}
+
+#[test]
+fn noinfo_test() {
+    // With no info lines to hide, firstnumber is the real first line number and the -3/-2
+    // guards disappear from the line number command entirely
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 noinfo noscopes"
+    ));
+    assert!(latex.contains("\\begin{minted}[linenos,firstnumber=45]{python}"));
+    assert!(latex.contains(
+        "{\\renewcommand{\\theFancyVerbLine}{\\textcolor[rgb]{0.5,0.5,1}{\\arabic{FancyVerbLine}}}"
+    ));
+    assert!(latex.contains("{python}\n    def __init__(self):\n"));
+}
+
+#[test]
+fn highlight_rel_test() {
+    // Relative ranges are counted within the displayed body, so lines 2-3 of the snippet are
+    // file lines 46-47
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 highlight_rel=2-3 noscopes"
+    ));
+    assert!(latex.contains("highlightlines={46-47}"));
+
+    // With multiple ranges, the relative numbering skips the hidden gap lines
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45,55-56 highlight_rel=2 noscopes"
+    ));
+    assert!(latex.contains("highlightlines={55}"));
+}
+
+#[test]
+fn directory_snippet_test() {
+    let latex = get_latex(&format!("%: {TEST_HASH}\n%: src/lintrans/typing_"));
+
+    // Every file in the directory appears, in sorted order, prefixed by its own filename
+    let init_position = latex
+        .find("# src/lintrans/typing_/__init__.py")
+        .expect("__init__.py should be listed");
+    let py_typed_position = latex
+        .find("# src/lintrans/typing_/py.typed")
+        .expect("py.typed should be listed");
+    assert!(init_position < py_typed_position);
+    assert!(latex.contains("is_matrix_type"));
+}
+
+#[test]
+fn blame_test() {
+    // The baseline repo only has one commit, so it must be the one that touched every line
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 blame noscopes"
+    ));
+    assert!(latex.contains(&format!("# touched by: {}\n", &TEST_HASH[..8])));
+
+    // The extra info line shifts firstnumber by one more
+    assert!(latex.contains("firstnumber=41"));
+}
+
+#[test]
+fn renumber_test() {
+    // The scope line shows as 1 and the body continues from 2, with the gap still compressed
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 renumber"
+    ));
+    assert!(latex.contains("firstnumber=-2"));
+    assert!(latex.contains("\\ifnum\\value{FancyVerbLine}=2... \\else"));
+    assert!(latex.contains("\\the\\numexpr\\value{FancyVerbLine}-1\\relax"));
+}
+
+#[test]
+fn highlight_regex_test() {
+    // Only line 47 of the sample mentions _matrices, and the manual highlight is unioned in
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 highlight=45 highlight_regex=\"self\\._matrices\" noscopes"
+    ));
+    assert!(latex.contains("highlightlines={45,47}"));
+}
+
+#[test]
+fn ellipsis_test() {
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45,55-56 ellipsis=\"[...] \" noscopes"
+    ));
+    assert!(latex.contains("\\ifnum\\value{FancyVerbLine}=46[...] \\else"));
+    assert!(!latex.contains("=46... "));
+}
+
+#[test]
+fn breaklines_test() {
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 breaklines noscopes"
+    ));
+    assert!(latex.contains("\\begin{minted}[linenos,firstnumber=42,breaklines]{python}"));
+}
+
+#[test]
+fn caption_test() {
+    // caption=commit pulls the summary line of the pinned commit from the repo
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 caption=commit noscopes"
+    ));
+    assert!(latex.ends_with("\\end{minted}\n\\captionof{listing}{baseline}\n}"));
+
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 caption=\"The wrapper\" noscopes"
+    ));
+    assert!(latex.contains("\\captionof{listing}{The wrapper}"));
+}
+
+#[test]
+fn diff_test() {
+    // Diffing a file against the same commit gives an empty diff, rendered with the diff lexer
+    let latex = get_latex(&format!("%: {TEST_HASH}\n%: compile.py diff={TEST_HASH}"));
+    assert!(latex.contains("{diff}"));
+    assert!(!latex.contains("@@"));
+}
+
+#[test]
+fn marker_test() {
+    // The lines between the markers are included; the marker lines themselves are not
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: misc/marker_example.py marker=parser noinfo noscopes"
+    ));
+    assert!(latex.contains("\\begin{minted}[linenos,firstnumber=5]{python}"));
+    assert!(latex.contains("def parse():\n    return 42"));
+    assert!(!latex.contains("SNIPPET-START"));
+
+    // show_markers keeps the delimiting lines in the body
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: misc/marker_example.py marker=parser noinfo noscopes show_markers"
+    ));
+    assert!(latex.contains("\\begin{minted}[linenos,firstnumber=4]{python}"));
+    assert!(latex.contains("# SNIPPET-START parser\ndef parse():"));
+    assert!(latex.contains("# SNIPPET-END parser"));
+}
+
+#[test]
+fn compact_scopes_test() {
+    // The decorator and def chain collapses onto one line, trimmed and joined with "..."
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: misc/decorated_example.py:9 compact_scopes noinfo"
+    ));
+    assert!(latex.contains("class Widget: ... @property ... def size(self): ...\n"));
+    assert!(!latex.contains("    @property"));
+
+    // The compacted line is numbered as the outermost header
+    assert!(latex.contains("firstnumber=4"));
+}
+
+#[test]
+fn adjacent_gap_test() {
+    // The def sits directly above the body, so they join one chunk; the two lines skipped
+    // after the class header still get their ellipsis
+    let latex = get_latex(&format!("%: {TEST_HASH}\n%: misc/decorated_example.py:9"));
+    assert!(latex.contains("    def size(self):\n        return 42"));
+    assert!(latex.contains("\\ifnum\\value{FancyVerbLine}=5... \\else"));
+
+    // A gap that skips only line 46 shows no ellipsis at all
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45,47-48 noscopes"
+    ));
+    assert!(latex.contains("\\ifnum\\value{FancyVerbLine}=46\\else"));
+    assert!(!latex.contains("=46... "));
+}
+
+#[test]
+fn float_test() {
+    // The whole block sits inside a listing float, with the placement passed through
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: compile.py float language=text noscopes"
+    ));
+    assert!(latex.starts_with("\\begin{listing}\n{"));
+    assert!(latex.ends_with("}\n\\end{listing}"));
+
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: compile.py float=htbp language=text noscopes"
+    ));
+    assert!(latex.starts_with("\\begin{listing}[htbp]\n{"));
+}
+
+#[test]
+fn linenumcolor_test() {
+    // A named color substitutes directly; an rgb triple keeps the [rgb] model argument
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: compile.py language=text linenumcolor=gray noscopes"
+    ));
+    assert!(latex.contains("\\textcolor{gray}{"));
+
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: compile.py language=text linenumcolor=0.8,0.2,0.2 noscopes"
+    ));
+    assert!(latex.contains("\\textcolor[rgb]{0.8,0.2,0.2}{"));
+
+    let latex = get_latex(&format!("%: {TEST_HASH}\n%: compile.py language=text noscopes"));
+    assert!(latex.contains("\\textcolor[rgb]{0.5,0.5,1}{"));
+}
+
+#[test]
+fn elide_test() {
+    // Eliding 46-54 renders the same bodies as the explicit ranges 45,55-56
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 elide=46-54 noscopes"
+    ));
+    let explicit = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45,55-56 noscopes"
+    ));
+    assert_eq!(latex, explicit);
+}
+
+#[test]
+fn malformed_comment_test() {
+    // The second comment matches the pattern but has unparseable options, so it's left in the
+    // output verbatim while the first still expands
+    let contents = format!(
+        "%: {TEST_HASH}\n%: compile.py language=text noscopes\n\n\
+         %: {TEST_HASH}\n%: compile.py nonsense_option=???\n"
+    );
+    let processed = process_snippets(&get_repo(), &contents, Verbosity::Quiet, &mut vec![])
+        .expect("The good comment should still process");
+
+    assert!(processed.contains("\\begin{minted}"));
+    assert!(processed.contains(&format!("%: {TEST_HASH}\n%: compile.py nonsense_option=???")));
+}
+
+#[test]
+fn language_quoting_test() {
+    // Custom lexer invocations are quoted whether or not they use -x; a plain language is not
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: compile.py noscopes\n%: {{ language = \"lexers.py:MyLexer -x\" }}"
+    ));
+    assert!(latex.contains("{'lexers.py:MyLexer -x'}"));
+
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: compile.py noscopes\n%: {{ language = \"lexers.py:MyLexer -f options\" }}"
+    ));
+    assert!(latex.contains("{'lexers.py:MyLexer -f options'}"));
+
+    let latex = get_latex(&format!("%: {TEST_HASH}\n%: compile.py language=text noscopes"));
+    assert!(latex.contains("{text}"));
+}
+
+#[test]
+fn highlight_diff_test() {
+    // Diffing against the same commit changes nothing, so no highlights are added
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 highlight_diff={TEST_HASH} noscopes"
+    ));
+    assert!(!latex.contains("highlightlines"));
+
+    // Manual highlights still come through unchanged
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 highlight=45 highlight_diff={TEST_HASH} noscopes"
+    ));
+    assert!(latex.contains("highlightlines={45}"));
+}
+
+#[test]
+fn verbatim_backend_test() {
+    // The verbatim backend swaps minted for fancyvrb's Verbatim, keeping the line number
+    // machinery and provenance comments but needing no Pygments
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 backend=verbatim noscopes"
+    ));
+    assert!(latex.contains("\\begin{Verbatim}[numbers=left,firstnumber=42]"));
+    assert!(latex.contains("\\end{Verbatim}"));
+    assert!(latex.contains("\\renewcommand{\\theFancyVerbLine}"));
+    assert!(!latex.contains("minted"));
+}
+
+#[test]
+fn firstnumber_rebase_test() {
+    // firstnumber=1 shifts every displayed number down so the first body line shows 1, with
+    // the gap machinery intact
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45,55-56 firstnumber=1 noscopes"
+    ));
+    assert!(latex.contains("firstnumber=-2"));
+    assert!(latex.contains("\\ifnum\\value{FancyVerbLine}=2... \\else"));
+    assert!(latex.contains("+8\\relax"));
+}
+
+#[test]
+fn frame_test() {
+    // frame and framesep pass straight through to minted; unknown frame styles fail to parse
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45 frame=single framesep=2mm noscopes"
+    ));
+    assert!(latex.contains("frame=single,framesep=2mm]{python}"));
+
+    assert!(crate::config::Config::parse("frame=fancy noscopes").is_err());
+}
+
+#[test]
+fn numbersep_test() {
+    // numbersep passes straight through to minted, pushing the gutter away from deeply
+    // nested code
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45 numbersep=12pt noscopes"
+    ));
+    assert!(latex.contains("numbersep=12pt]{python}"));
+}
+
+#[test]
+fn exclude_test() {
+    // Excluding a single interior line splits the range around it and shows an ellipsis in
+    // the gap, where a naturally skipped single line wouldn't get one
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 exclude=50 noscopes"
+    ));
+    assert!(latex.contains("\\ifnum\\value{FancyVerbLine}=50... \\else"));
+    assert!(latex.contains("firstnumber=42"));
+
+    // An exclusion that removes every line is an error, not a panic
+    let comment = Comment::from_latex_comment(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45 exclude=45 noscopes"
+    ))
+    .unwrap();
+    assert!(comment.get_text(&get_repo()).is_err());
+}
+
+#[test]
+fn tag_test() {
+    // Pinning to a tag keeps the provenance human-meaningful; the resolved commit rides
+    // along in the info comment so the listing stays reproducible
+    let latex = get_latex("%: v9.9\n%: compile.py noscopes");
+    assert!(latex.contains(&format!("# v9.9 ({})\n# compile.py", &TEST_HASH[..8])));
+    assert!(latex.contains("A simple compile script"));
+
+    let comment = Comment::from_latex_comment("%: v9.9\n%: compile.py noscopes").unwrap();
+    assert!(comment.details().starts_with("v9.9 compile.py"));
+}
+
+#[test]
+fn stepnumber_test() {
+    // stepnumber only renders a number when the displayed value divides by N, with the
+    // divisibility test built into the macro so gap offsets don't misalign it
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45-56 stepnumber=5 noscopes"
+    ));
+    assert!(latex.contains(
+        "\\ifnum\\numexpr\\value{FancyVerbLine}-\\value{FancyVerbLine}/5*5\\relax=0 \
+         \\arabic{FancyVerbLine}\\fi"
+    ));
+    assert!(latex.contains("\\ifnum\\value{FancyVerbLine}<44\\else"));
+}
+
+#[test]
+fn max_lines_test() {
+    // A snippet over the --max-lines limit warns, unless it opts out with allow_long
+    crate::config::set_max_lines(200);
+    let before = crate::warnings::count();
+    get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py noscopes"
+    ));
+    assert!(crate::warnings::count() > before);
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py allow_long noscopes"
+    ));
+    assert!(latex.contains("class MatrixWrapper"));
+}
+
+#[test]
+fn head_placeholder_test() {
+    // A HEAD placeholder is substituted with the hash set at startup, so the info comment
+    // records the concrete commit
+    crate::comment::set_head_hash(TEST_HASH);
+    let latex = get_latex("%: HEAD\n%: src/lintrans/matrices/wrapper.py:45 noscopes");
+    assert!(latex.contains(&format!("# {TEST_HASH}")));
+    assert!(!latex.contains("HEAD"));
+
+    // The rewrite modes keep the placeholder as written, so the source stays
+    // continuously-updating instead of being pinned behind the author's back
+    let comment = Comment::from_latex_comment("%: HEAD\n%: compile.py noscopes").unwrap();
+    assert_eq!(comment.hash, TEST_HASH);
+    assert_eq!(comment.hash_as_written, "HEAD");
+    assert!(comment.to_comment_string().starts_with("%: HEAD\n"));
+}
+
+#[test]
+fn info_as_caption_test() {
+    // The provenance becomes the caption and no info comment lines are emitted
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45 info_as_caption noscopes"
+    ));
+    assert!(latex.contains(&format!(
+        "\\captionof{{listing}}{{\\texttt{{{TEST_HASH} src/lintrans/matrices/wrapper.py}}}}"
+    )));
+    assert!(!latex.contains(&format!("# {TEST_HASH}")));
+    assert!(latex.contains("firstnumber=45"));
+}
+
+#[test]
+fn info_position_trailing_test() {
+    // A trailing info position puts the provenance at the end of the first body line, so the
+    // numbering needs no offset and no header guard at all
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45 info_position=trailing noscopes"
+    ));
+    assert!(latex.contains(&format!("  # {TEST_HASH} src/lintrans/matrices/wrapper.py")));
+    assert!(latex.contains("firstnumber=45"));
+    assert!(!latex.contains("\\ifnum"));
+}
+
+#[test]
+fn langs_test() {
+    // With langs, each range renders as its own minted environment with the matching lexer,
+    // all inside one group; only the first carries the info comment lines
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45,55-56 langs=python,text noscopes"
+    ));
+    assert!(latex.contains("\\begin{minted}[linenos,firstnumber=42]{python}"));
+    assert!(latex.contains("\\begin{minted}[linenos,firstnumber=55]{text}"));
+    assert!(latex.contains("\\ifnum\\value{FancyVerbLine}<44\\else"));
+    assert_eq!(latex.matches("\\end{minted}").count(), 2);
+
+    // The body lines sit flush against the environment, with no indentation injected by the
+    // formatting and the info lines only in the first environment
+    assert!(latex.contains(&format!("{{python}}\n# {TEST_HASH}\n")));
+    assert!(latex.contains("{text}\n            'Z': None\n        }\n\\end{minted}"));
+}
+
+#[test]
+fn worktree_test() {
+    // The WORKTREE hash reads the file straight from the fixture repo's working tree, which
+    // matches the committed content since nothing has been edited
+    let latex = get_latex("%: WORKTREE\n%: compile.py language=text noscopes");
+    assert!(latex.contains("# WORKTREE\n# compile.py"));
+    assert!(latex.contains("A simple compile script"));
+    assert!(!latex.contains(TEST_HASH));
+}
+
+#[test]
+fn multiple_ranges_test() {
+    let latex = get_latex(&format!(
+        "%: {TEST_HASH}\n%: src/lintrans/matrices/wrapper.py:45,55-56 noscopes"
+    ));
+    assert_eq!(
+        latex,
+        format!(
+            r"{{\renewcommand{{\theFancyVerbLine}}{{\textcolor[rgb]{{0.5,0.5,1}}{{\ifnum\value{{FancyVerbLine}}<44\else\ifnum\value{{FancyVerbLine}}<46\arabic{{FancyVerbLine}}\else\ifnum\value{{FancyVerbLine}}=46... \else\the\numexpr\value{{FancyVerbLine}}+8\relax\fi\fi\fi}}}}
+\begin{{minted}}[linenos,firstnumber=42]{{python}}
+# {TEST_HASH}
+# src/lintrans/matrices/wrapper.py
+
+    def __init__(self):
+
+            'Z': None
+        }}
+\end{{minted}}
+}}"
+        )
+    );
+}
@@ -0,0 +1,1188 @@
+// lintrans - The linear transformation visualizer
+// Copyright (C) 2021-2022 D. Dyson (DoctorDalek1963)
+
+// This program is licensed under GNU GPLv3, available here:
+// <https://www.gnu.org/licenses/gpl-3.0.html>
+
+//! This crate provides a simple binary to process code snippet comments in the LaTeX write-up.
+//!
+//! Every snippet is identified by a comment matching [`COMMENT_PATTERN`](comment::COMMENT_PATTERN)
+//! in one of the ``.tex`` files. We replace each comment with a ``minted`` environment containing
+//! the referenced lines of the referenced file, taken from the lintrans git history, and write the
+//! result to a new file with a ``processed_`` prefix (configurable with ``--prefix`` and
+//! ``--output-dir``).
+
+mod comment;
+mod config;
+mod languages;
+#[cfg(test)]
+mod latex_tests;
+mod text;
+mod warnings;
+
+use color_eyre::{eyre::eyre, Result};
+use git2::{Oid, Repository};
+use notify::{RecursiveMode, Watcher};
+use rayon::prelude::*;
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, IsTerminal, Read},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+use crate::comment::{Comment, COMMENT_PATTERN};
+
+/// A machine-readable record of one expanded snippet, written by ``--manifest-out``.
+#[derive(Clone, Debug, serde::Serialize)]
+struct SnippetReport {
+    /// The ``.tex`` file the snippet comment was found in, or empty for stdin.
+    source: PathBuf,
+
+    /// The hash of the commit the snippet was taken from.
+    hash: String,
+
+    /// The file the snippet was taken from.
+    filename: PathBuf,
+
+    /// The resolved line ranges, as ``first-last`` strings.
+    ranges: Vec<String>,
+
+    /// The detected scope lines, as ``number: line`` strings.
+    scopes: Vec<String>,
+
+    /// The resolved caption, if the snippet has one.
+    caption: Option<String>,
+
+    /// The raw body text, kept only for ``--emit-sidecars``.
+    #[serde(skip)]
+    raw_body: String,
+
+    /// The number of body lines embedded in the generated LaTeX.
+    body_lines: usize,
+
+    /// The length of the generated LaTeX, in bytes.
+    output_bytes: usize,
+}
+
+/// How much detail to print while processing files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    /// Print nothing but errors.
+    Quiet,
+
+    /// Print each filename and the details of each comment.
+    Normal,
+
+    /// Additionally print resolved ranges, detected scopes, and output sizes.
+    Verbose,
+}
+
+/// Expand the given arguments into the list of files to process.
+///
+/// A directory argument expands to the ``.tex`` files directly inside it, or to every ``.tex``
+/// file below it when `recursive` is set. Any other argument is treated as a glob pattern, which
+/// also covers plain filenames.
+fn expand_patterns(patterns: &[String], recursive: bool) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+
+    for pattern in patterns {
+        let tex_glob = if Path::new(pattern).is_dir() {
+            if recursive {
+                format!("{pattern}/**/*.tex")
+            } else {
+                format!("{pattern}/*.tex")
+            }
+        } else {
+            pattern.clone()
+        };
+
+        let mut matched_any = false;
+        for entry in glob::glob(&tex_glob)? {
+            paths.push(entry?);
+            matched_any = true;
+        }
+        if !matched_any {
+            return Err(eyre!("No files match {pattern:?}"));
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Process every snippet comment in the given file and write the result to a prefixed copy.
+///
+/// The output lands next to the input, or in `output_dir` if one was given, with `prefix`
+/// prepended to the filename. Files without any snippet comments are skipped entirely; the
+/// return value is `None` for a skipped file. In `combined` mode nothing is written here:
+/// the processed body is returned for the caller to concatenate.
+#[allow(clippy::too_many_arguments)]
+fn process_all_snippets_in_file(
+    repo: &Repository,
+    path: &Path,
+    prefix: &str,
+    output_dir: Option<&Path>,
+    in_place: bool,
+    combined: bool,
+    verbosity: Verbosity,
+    reports: &mut Vec<SnippetReport>,
+) -> Result<Option<String>> {
+    let contents = fs::read_to_string(path)?;
+
+    if !COMMENT_PATTERN.is_match(&contents) {
+        return Ok(None);
+    }
+
+    if verbosity >= Verbosity::Normal {
+        println!("{}", path.display());
+    }
+
+    let body = process_snippets(repo, &contents, verbosity, reports)?;
+    for report in reports.iter_mut().filter(|report| report.source.as_os_str().is_empty()) {
+        report.source = path.to_path_buf();
+    }
+
+    // A combined run writes one file for the whole pass, so there is no per-file output
+    if combined {
+        return Ok(Some(body));
+    }
+
+    let new_filename = format!(
+        "{prefix}{}",
+        path.file_name()
+            .ok_or_else(|| eyre!("Path {path:?} has no filename"))?
+            .to_string_lossy()
+    );
+    let new_path = match output_dir {
+        Some(dir) => dir.join(new_filename),
+        None => path.with_file_name(new_filename),
+    };
+
+    // Compare the canonicalized paths, since e.g. --output-dir . can make the output path
+    // spell the input path differently while still clobbering it
+    let canonical_input = path.canonicalize()?;
+    let canonical_output = match new_path.parent() {
+        Some(parent) if parent != Path::new("") => parent.canonicalize()?.join(
+            new_path
+                .file_name()
+                .expect("Output path should have a filename"),
+        ),
+        _ => new_path.canonicalize().unwrap_or_else(|_| new_path.clone()),
+    };
+    if canonical_input == canonical_output && !in_place {
+        return Err(eyre!(
+            "Refusing to overwrite input file {} (pass --in-place to allow this)",
+            path.display()
+        ));
+    }
+
+    if config::emit_sidecars() {
+        let sidecar_dir = canonical_output
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        write_sidecars(&sidecar_dir, reports)?;
+    }
+
+    fs::write(new_path, body)?;
+
+    Ok(Some(String::new()))
+}
+
+/// Write the raw body of each snippet to a copyable sidecar file in the given directory.
+///
+/// The file is named after the snippet's caption when it has one, and after its source file,
+/// hash, and first range otherwise, always keeping the source file's extension so editors
+/// highlight it sensibly.
+fn write_sidecars(dir: &Path, reports: &[SnippetReport]) -> Result<()> {
+    for report in reports {
+        let stem = match &report.caption {
+            Some(caption) => slugify(caption),
+            None => format!(
+                "{}-{}-{}",
+                report
+                    .filename
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| String::from("snippet")),
+                comment::short_hash(&report.hash),
+                report.ranges.first().map_or("all", String::as_str)
+            ),
+        };
+        let extension = report
+            .filename
+            .extension()
+            .map(|extension| format!(".{}", extension.to_string_lossy()))
+            .unwrap_or_default();
+
+        fs::write(dir.join(format!("{stem}{extension}")), format!("{}\n", report.raw_body))?;
+    }
+
+    Ok(())
+}
+
+/// Turn a caption into a filesystem-safe filename stem.
+fn slugify(caption: &str) -> String {
+    let mut slug = String::new();
+    for c in caption.to_lowercase().chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.ends_with('-') && !slug.is_empty() {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Print a deduplicated listing of every snippet referenced in the given files.
+///
+/// This is a read-only audit: no ``processed_`` files are written. The snippets are grouped by
+/// commit, with each unique combination of filename, ranges, and options listed once.
+fn list_all_snippets(paths: &[PathBuf]) -> Result<()> {
+    let mut details: Vec<String> = vec![];
+    for path in paths {
+        let contents = fs::read_to_string(path)?;
+        for m in COMMENT_PATTERN.find_iter(&contents) {
+            if let Some(comment) = Comment::from_latex_comment(m.as_str()) {
+                details.push(comment.details());
+            }
+        }
+    }
+    details.sort();
+    details.dedup();
+
+    let mut last_hash = "";
+    for detail in &details {
+        let (hash, rest) = detail.split_once(' ').unwrap_or((detail, ""));
+        if hash != last_hash {
+            println!("{hash}:");
+            last_hash = hash;
+        }
+        println!("  {rest}");
+    }
+
+    Ok(())
+}
+
+/// Check that every snippet in the given files points at a commit that exists in the repo and
+/// is an ancestor of ``HEAD``, without writing any files.
+///
+/// After a force-push or history rewrite, a pinned commit can disappear or end up off-branch,
+/// and [`Comment::get_text`] then fails obscurely on ``find_commit``. This flags exactly which
+/// snippets need re-pinning.
+fn check_all_snippets(repo: &Repository, paths: &[PathBuf]) -> Result<()> {
+    let head = repo.head()?.peel_to_commit()?.id();
+    let mut problems: u32 = 0;
+
+    for path in paths {
+        let contents = fs::read_to_string(path)?;
+        for m in COMMENT_PATTERN.find_iter(&contents) {
+            let Some(comment) = Comment::from_latex_comment(m.as_str()) else {
+                continue;
+            };
+
+            let oid = match comment.resolve_oid(repo) {
+                Ok(oid) => oid,
+                Err(error) => {
+                    warnings::warn(&format!(
+                        "{}: {}: {error}",
+                        path.display(),
+                        comment.details()
+                    ));
+                    problems += 1;
+                    continue;
+                }
+            };
+            if oid != head && !repo.graph_descendant_of(head, oid)? {
+                warnings::warn(&format!(
+                    "{}: {} points at a commit that isn't an ancestor of HEAD",
+                    path.display(),
+                    comment.details()
+                ));
+                problems += 1;
+            }
+        }
+    }
+
+    if problems > 0 {
+        Err(eyre!("{problems} snippet(s) need re-pinning"))
+    } else {
+        println!("All snippet commits are reachable from HEAD");
+        Ok(())
+    }
+}
+
+/// Report, for each snippet in the given files, whether its pinned lines still match the
+/// same path at ``HEAD``, without writing any files.
+///
+/// This triages which listings have gone stale after development continued: an "unchanged"
+/// snippet can be re-pinned freely, a "changed" one needs its prose re-checked first, and a
+/// missing file needs a new path. Unlike ``--update-hashes``, nothing is rewritten.
+fn check_all_drift(repo: &Repository, paths: &[PathBuf]) -> Result<()> {
+    let head = repo.head()?.peel_to_commit()?.id().to_string();
+
+    for path in paths {
+        let contents = fs::read_to_string(path)?;
+        for m in COMMENT_PATTERN.find_iter(&contents) {
+            let Some(comment) = Comment::from_latex_comment(m.as_str()) else {
+                continue;
+            };
+            // A WORKTREE snippet tracks the working tree by construction, so drift against
+            // HEAD means nothing for it
+            if comment.hash == "WORKTREE" {
+                continue;
+            }
+            let details = comment.details();
+
+            let pinned = match comment.clone().get_text(repo) {
+                Ok(text) => text,
+                Err(error) => {
+                    warnings::warn(&format!("{}: {details}: {error}", path.display()));
+                    continue;
+                }
+            };
+
+            let mut at_head = comment;
+            at_head.hash = head.clone();
+            let status = match at_head.get_text(repo) {
+                Ok(text) if text.bodies == pinned.bodies => "unchanged",
+                Ok(_) => "changed",
+                Err(comment::SnippetError::MissingFile { .. }) => "file missing at HEAD",
+                // The same ranges not resolving at HEAD (shrunk file, moved marker) is
+                // still drift
+                Err(_) => "changed",
+            };
+            println!("{}: {details}: {status}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Report every file under the given source root that no snippet in the given files points to.
+///
+/// This is a read-only completeness audit for an "annotated source" style of document: the
+/// referenced filenames are collected from every comment, and the files under the root (which
+/// should be the repo's working tree, so the relative paths line up) are checked against them.
+/// A directory snippet counts as referencing every file directly inside its directory.
+fn list_unreferenced_files(paths: &[PathBuf], source_root: &Path) -> Result<()> {
+    let mut referenced: Vec<PathBuf> = vec![];
+    for path in paths {
+        let contents = fs::read_to_string(path)?;
+        for m in COMMENT_PATTERN.find_iter(&contents) {
+            if let Some(comment) = Comment::from_latex_comment(m.as_str()) {
+                referenced.push(comment.filename);
+            }
+        }
+    }
+
+    let mut files: Vec<PathBuf> = vec![];
+    collect_source_files(source_root, source_root, &mut files)?;
+    files.sort();
+
+    let mut unreferenced = 0;
+    for file in &files {
+        let covered = referenced.iter().any(|reference| {
+            reference == file || Some(reference.as_path()) == file.parent()
+        });
+        if !covered {
+            println!("{}", file.display());
+            unreferenced += 1;
+        }
+    }
+
+    if unreferenced == 0 {
+        println!("All {} file(s) under {} are referenced", files.len(), source_root.display());
+    }
+
+    Ok(())
+}
+
+/// Collect every file below `dir` into `files`, relative to `root`, skipping dot-entries like
+/// ``.git``.
+fn collect_source_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let path = entry.path();
+        if path.is_dir() {
+            collect_source_files(root, &path, files)?;
+        } else {
+            files.push(path.strip_prefix(root)?.to_path_buf());
+        }
+    }
+
+    Ok(())
+}
+
+/// Rewrite every snippet comment in the given files into its canonical form, without
+/// expanding anything into LaTeX.
+///
+/// Abbreviated hashes expand to the full commit id, the options come out in
+/// [`Config::details`](config::Config::details)'s alphabetical order, and an inline TOML
+/// config flattens onto the filename line, so the comments stay tidy and diff-friendly.
+/// Running twice changes nothing. Tag, ``WORKTREE``, and ``HEAD`` pins are symbolic on
+/// purpose and stay as written, and ``@name`` references are already as tidy as they get.
+fn canonicalize_all_snippets(repo: &Repository, paths: &[PathBuf], in_place: bool) -> Result<()> {
+    if !in_place {
+        return Err(eyre!(
+            "--canonicalize rewrites the source files; pass --in-place to allow this"
+        ));
+    }
+
+    for path in paths {
+        let contents = fs::read_to_string(path)?;
+        let mut replacements: Vec<(std::ops::Range<usize>, String)> = vec![];
+
+        for captures in COMMENT_PATTERN.captures_iter(&contents) {
+            if captures.name("name").is_some() {
+                continue;
+            }
+            let m = captures.get(0).unwrap();
+            let Some(mut comment) = Comment::from_latex_comment(m.as_str()) else {
+                continue;
+            };
+
+            if comment.hash_as_written.len() < 40
+                && comment.hash_as_written.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                match comment.resolve_oid(repo) {
+                    Ok(oid) => {
+                        comment.hash = oid.to_string();
+                        comment.hash_as_written = comment.hash.clone();
+                    }
+                    Err(error) => {
+                        warnings::warn(&format!(
+                            "{}: {}: {error}",
+                            path.display(),
+                            comment.details()
+                        ));
+                        continue;
+                    }
+                }
+            }
+
+            // An option value that can't survive the flat syntax (a space-containing
+            // language from an inline TOML table, say) would corrupt the comment, so
+            // anything that doesn't round-trip stays as written
+            let canonical = comment.to_comment_string();
+            if Comment::from_latex_comment(&canonical).as_ref() != Some(&comment) {
+                warnings::warn(&format!(
+                    "{}: {}: doesn't survive canonicalization, leaving it as written",
+                    path.display(),
+                    comment.details()
+                ));
+                continue;
+            }
+            if canonical != m.as_str() {
+                replacements.push((m.range(), canonical));
+            }
+        }
+
+        if !replacements.is_empty() {
+            println!(
+                "{}: canonicalized {} snippet comment(s)",
+                path.display(),
+                replacements.len()
+            );
+            let mut body = contents.clone();
+            for (range, canonical) in replacements.iter().rev() {
+                body.replace_range(range.clone(), canonical);
+            }
+            fs::write(path, body)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Re-pin every snippet in the given files to the newest commit touching its file.
+///
+/// For each comment, the commits reachable from ``HEAD`` are walked newest-first to find the
+/// most recent one that changed the snippet's file; the snippet is re-resolved at that commit
+/// and the hash is rewritten in place only when that resolution succeeds, so a range that no
+/// longer exists keeps its old pin (with a warning).
+fn update_all_hashes(repo: &Repository, paths: &[PathBuf]) -> Result<()> {
+    for path in paths {
+        let contents = fs::read_to_string(path)?;
+        let mut replacements: Vec<(std::ops::Range<usize>, String)> = vec![];
+
+        for captures in COMMENT_PATTERN.captures_iter(&contents) {
+            let Some(comment) =
+                Comment::from_latex_comment(captures.get(0).unwrap().as_str())
+            else {
+                continue;
+            };
+            // An @name reference has no hash of its own to rewrite, a WORKTREE snippet is
+            // deliberately unpinned, a tag pin is deliberately stable, and a HEAD
+            // placeholder deliberately re-resolves at every build
+            let Some(hash_match) = captures.name("hash") else {
+                continue;
+            };
+            if comment.hash_as_written == "WORKTREE"
+                || !comment.hash_as_written.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                continue;
+            }
+
+            let Some(newest) = newest_commit_touching(repo, &comment.filename)? else {
+                warnings::warn(&format!(
+                    "{}: no commit reachable from HEAD touches {}",
+                    path.display(),
+                    comment.filename.display()
+                ));
+                continue;
+            };
+            let newest = newest.to_string();
+            if newest == comment.hash {
+                continue;
+            }
+
+            let mut updated = comment.clone();
+            updated.hash = newest.clone();
+            if updated.get_text(repo).is_err() {
+                warnings::warn(&format!(
+                    "{}: {} doesn't resolve at {}, keeping the old pin",
+                    path.display(),
+                    comment.details(),
+                    &newest[..8]
+                ));
+                continue;
+            }
+
+            println!(
+                "{}: {} -> {} for {}",
+                path.display(),
+                &comment.hash[..8],
+                &newest[..8],
+                comment.filename.display()
+            );
+            replacements.push((hash_match.range(), newest));
+        }
+
+        if !replacements.is_empty() {
+            let mut body = contents.clone();
+            for (range, hash) in replacements.iter().rev() {
+                body.replace_range(range.clone(), hash);
+            }
+            fs::write(path, body)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Return the newest commit reachable from ``HEAD`` that changed the given file, if any.
+///
+/// A commit counts as changing the file when no parent already had the same blob at the same
+/// path, which also makes a root commit count for every file it contains.
+fn newest_commit_touching(repo: &Repository, filename: &Path) -> Result<Option<Oid>> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+
+    for oid in revwalk {
+        let oid = oid?;
+        let commit = repo.find_commit(oid)?;
+        let Some(blob_id) = commit
+            .tree()?
+            .get_path(filename)
+            .ok()
+            .map(|entry| entry.id())
+        else {
+            continue;
+        };
+
+        let unchanged = commit.parents().any(|parent| {
+            parent
+                .tree()
+                .ok()
+                .and_then(|tree| tree.get_path(filename).ok())
+                .is_some_and(|entry| entry.id() == blob_id)
+        });
+        if !unchanged {
+            return Ok(Some(oid));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Warn about lines that look like snippet comments but don't match the strict pattern.
+///
+/// [`COMMENT_PATTERN`] silently skips a comment that's almost right - a stray space in the
+/// filename, say - which would leave a raw ``%:`` comment in the output. This pass flags every
+/// ``%:`` line that isn't part of a strict match, with its line number.
+fn warn_about_malformed_comments(contents: &str) {
+    let spans: Vec<std::ops::Range<usize>> = COMMENT_PATTERN
+        .find_iter(contents)
+        .map(|m| m.range())
+        .collect();
+
+    let mut offset = 0;
+    for (index, line) in contents.lines().enumerate() {
+        if line.starts_with("%:") && !spans.iter().any(|span| span.contains(&offset)) {
+            warnings::warn(&format!(
+                "line {} looks like a snippet comment but doesn't match \
+                 COMMENT_PATTERN: {line:?}",
+                index + 1
+            ));
+        }
+        offset += line.len() + 1;
+    }
+}
+
+/// Replace every snippet comment in the given string with its generated LaTeX, recording a
+/// [`SnippetReport`] for each one (with the source path left for the caller to fill in).
+fn process_snippets(
+    repo: &Repository,
+    contents: &str,
+    verbosity: Verbosity,
+    reports: &mut Vec<SnippetReport>,
+) -> Result<String> {
+    warn_about_malformed_comments(contents);
+
+    let mut seen: Vec<&str> = vec![];
+    let mut last_hashes: HashMap<PathBuf, String> = HashMap::new();
+    let replacements: Vec<(std::ops::Range<usize>, String)> = COMMENT_PATTERN
+        .find_iter(contents)
+        .map(|m| {
+            // A matched block can still fail to parse (bad options, say); leaving it
+            // untouched in the output lets the rest of a mid-edit file expand normally
+            let Some(mut comment) = Comment::from_latex_comment(m.as_str()) else {
+                warnings::warn(&format!(
+                    "leaving unparseable snippet comment untouched: {:?}",
+                    m.as_str()
+                ));
+                return Ok((m.range(), m.as_str().to_string()));
+            };
+
+            // highlight_diff=prev resolves to the previous snippet of the same file in this
+            // document, letting a tutorial highlight what changed at each step
+            if comment.config.highlight_diff.as_deref() == Some("prev") {
+                comment.config.highlight_diff =
+                    match last_hashes.get(&comment.filename) {
+                        Some(hash) if *hash != comment.hash => Some(hash.clone()),
+                        _ => {
+                            warnings::warn(&format!(
+                                "no previous snippet of {} to diff against",
+                                comment.filename.display()
+                            ));
+                            None
+                        }
+                    };
+            }
+            last_hashes.insert(comment.filename.clone(), comment.hash.clone());
+            if seen.contains(&m.as_str()) {
+                warnings::warn(&format!("duplicate snippet comment: {}", comment.details()));
+            } else {
+                seen.push(m.as_str());
+            }
+            if verbosity >= Verbosity::Normal {
+                println!("  {}", comment.details());
+            }
+
+            let text = comment.get_text(repo)?;
+            if verbosity == Verbosity::Verbose {
+                let ranges: Vec<String> = text
+                    .bodies
+                    .iter()
+                    .map(|body| format!("{}-{}", body.first, body.last))
+                    .collect();
+                println!("    resolved ranges: {}", ranges.join(","));
+                for (number, line) in &text.scopes {
+                    println!("    scope at line {number}: {line}");
+                }
+            }
+
+            let latex = text.get_latex();
+            if verbosity == Verbosity::Verbose {
+                println!("    output: {} bytes", latex.len());
+            }
+
+            reports.push(SnippetReport {
+                source: PathBuf::new(),
+                hash: text.hash.clone(),
+                filename: text.filename.clone(),
+                ranges: text
+                    .bodies
+                    .iter()
+                    .map(|body| format!("{}-{}", body.first, body.last))
+                    .collect(),
+                scopes: text
+                    .scopes
+                    .iter()
+                    .map(|(number, line)| format!("{number}: {line}"))
+                    .collect(),
+                caption: text.caption.clone(),
+                raw_body: text
+                    .bodies
+                    .iter()
+                    .map(|body| body.lines.join("\n"))
+                    .collect::<Vec<String>>()
+                    .join("\n\n"),
+                body_lines: text.bodies.iter().map(|body| body.lines.len()).sum(),
+                output_bytes: latex.len(),
+            });
+
+            Ok((m.range(), latex))
+        })
+        .collect::<Result<_>>()?;
+
+    // Splice at the exact byte offsets of the matches, in reverse order so that earlier
+    // offsets stay valid as the replacements change the length of the body
+    let mut body = contents.to_string();
+    for (range, latex) in replacements.iter().rev() {
+        body.replace_range(range.clone(), latex);
+    }
+
+    Ok(body)
+}
+
+/// Install color_eyre's error hooks with every ANSI style disabled, for --color=never and
+/// for --color=auto without a terminal.
+fn install_colorless_eyre() -> Result<()> {
+    color_eyre::config::HookBuilder::new()
+        .theme(color_eyre::config::Theme::new())
+        .install()?;
+    Ok(())
+}
+
+/// Write the snippet reports to the given path as pretty-printed JSON.
+fn write_manifest(path: &Path, reports: &[SnippetReport]) -> Result<()> {
+    fs::write(path, format!("{}\n", serde_json::to_string_pretty(reports)?))?;
+    Ok(())
+}
+
+/// Run one full processing pass over the input files, writing the outputs, the manifest, and
+/// the summary line.
+///
+/// Files are processed in a bounded thread pool. git2's Repository isn't Sync, so each file
+/// opens its own handle onto the repo.
+///
+/// With `combined_out`, the processed bodies are concatenated into that one file instead of
+/// a prefixed copy per input, in command-line argument order.
+#[allow(clippy::too_many_arguments)]
+fn run_processing_pass(
+    repo_path: &str,
+    paths: &[PathBuf],
+    prefix: &str,
+    output_dir: Option<&Path>,
+    in_place: bool,
+    jobs: Option<usize>,
+    verbosity: Verbosity,
+    manifest_out: Option<&Path>,
+    combined_out: Option<&Path>,
+    summary: bool,
+) -> Result<()> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs.unwrap_or(1))
+        .build()?;
+    let results = pool.install(|| {
+        paths
+            .par_iter()
+            .map(|path| {
+                let repo = Repository::open(repo_path)?;
+                let mut reports = vec![];
+                let processed = process_all_snippets_in_file(
+                    &repo,
+                    path,
+                    prefix,
+                    output_dir,
+                    in_place,
+                    combined_out.is_some(),
+                    verbosity,
+                    &mut reports,
+                )?;
+                Ok((processed, reports))
+            })
+            .collect::<Result<Vec<(Option<String>, Vec<SnippetReport>)>>>()
+    })?;
+    let touched = results.iter().filter(|(processed, _)| processed.is_some()).count();
+
+    // The parallel collect keeps the files in argument order, so the combined output is
+    // deterministic; each fragment sits under a comment naming its source file
+    if let Some(combined_path) = combined_out {
+        let mut combined = String::new();
+        for (path, (body, _)) in paths.iter().zip(&results) {
+            if let Some(body) = body {
+                if !combined.is_empty() {
+                    combined.push('\n');
+                }
+                combined.push_str(&format!("%% {}\n{body}", path.display()));
+            }
+        }
+        fs::write(combined_path, combined)?;
+    }
+
+    let reports: Vec<SnippetReport> = results
+        .into_iter()
+        .flat_map(|(_, reports)| reports)
+        .collect();
+    if let Some(manifest_path) = manifest_out {
+        write_manifest(manifest_path, &reports)?;
+    }
+    if summary && verbosity >= Verbosity::Normal {
+        let body_lines: usize = reports.iter().map(|report| report.body_lines).sum();
+        println!(
+            "Processed {} snippet(s) across {touched} file(s), {body_lines} line(s) of code \
+             embedded",
+            reports.len()
+        );
+    } else if verbosity >= Verbosity::Normal {
+        println!("Processed {touched} file(s)");
+    }
+
+    Ok(())
+}
+
+/// Reprocess the input files whenever one of them (or the repo's ``HEAD``) changes on disk.
+///
+/// Editors typically fire several filesystem events per save, so after the first event we
+/// keep draining further events for half a second before reprocessing. A failing pass prints
+/// its error and keeps the watcher alive, so a mid-edit syntax error doesn't end the session.
+#[allow(clippy::too_many_arguments)]
+fn watch_files(
+    repo_path: &str,
+    paths: &[PathBuf],
+    prefix: &str,
+    output_dir: Option<&Path>,
+    in_place: bool,
+    jobs: Option<usize>,
+    verbosity: Verbosity,
+    manifest_out: Option<&Path>,
+    combined_out: Option<&Path>,
+    summary: bool,
+) -> Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    for path in paths {
+        watcher.watch(path, RecursiveMode::NonRecursive)?;
+    }
+
+    // Watching HEAD catches commits and checkouts, which can change what the snippets
+    // resolve to even though no .tex file was touched
+    let head_path = Path::new(repo_path).join(".git").join("HEAD");
+    if head_path.exists() {
+        watcher.watch(&head_path, RecursiveMode::NonRecursive)?;
+    }
+
+    println!("Watching {} file(s); press Ctrl-C to stop", paths.len());
+    loop {
+        if let Err(report) = run_processing_pass(
+            repo_path,
+            paths,
+            prefix,
+            output_dir,
+            in_place,
+            jobs,
+            verbosity,
+            manifest_out,
+            combined_out,
+            summary,
+        ) {
+            eprintln!("Error: {report}");
+        }
+
+        receiver.recv()??;
+        while receiver.recv_timeout(Duration::from_millis(500)).is_ok() {}
+        println!("Change detected, reprocessing");
+    }
+}
+
+fn main() -> Result<()> {
+    // color_eyre's hooks have to be installed before any Report is created, so the --color
+    // choice is scanned out of the arguments up front, ahead of the real argument loop
+    let mut color = String::from("auto");
+    {
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            if arg == "--color" {
+                if let Some(value) = args.next() {
+                    color = value;
+                }
+            } else if let Some(value) = arg.strip_prefix("--color=") {
+                color = value.to_string();
+            }
+        }
+    }
+    match color.as_str() {
+        "always" => color_eyre::install()?,
+        "never" => install_colorless_eyre()?,
+        "auto" if io::stderr().is_terminal() => color_eyre::install()?,
+        "auto" => install_colorless_eyre()?,
+        _ => return Err(eyre!("--color must be never, always, or auto")),
+    }
+
+    let mut recursive = false;
+    let mut list = false;
+    let mut check = false;
+    let mut check_drift = false;
+    let mut canonicalize = false;
+    let mut update_hashes = false;
+    let mut summary = false;
+    let mut list_unreferenced = false;
+    let mut source_root: Option<PathBuf> = None;
+    let mut fail_on_warning = false;
+    let mut jobs: Option<usize> = None;
+    let mut prefix = String::from("processed_");
+    let mut output_dir: Option<PathBuf> = None;
+    let mut in_place = false;
+    let mut watch = false;
+    let mut manifest_out: Option<PathBuf> = None;
+    let mut combined_out: Option<PathBuf> = None;
+    let mut verbosity = Verbosity::Normal;
+    let mut repo_path: Option<String> = None;
+    let mut worktree: Option<String> = None;
+    let mut copyright_pattern: Option<String> = None;
+    let mut patterns: Vec<String> = vec![];
+    let mut files_from: Option<PathBuf> = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--recursive" => recursive = true,
+            "--list" => list = true,
+            "--check" => check = true,
+            "--check-drift" => check_drift = true,
+            "--canonicalize" => canonicalize = true,
+            "--update-hashes" => update_hashes = true,
+            "--summary" => summary = true,
+            "--list-unreferenced" => list_unreferenced = true,
+            "--source-root" => {
+                source_root = Some(
+                    args.next()
+                        .ok_or_else(|| eyre!("--source-root needs a path"))?
+                        .into(),
+                )
+            }
+            "--follow-renames" => config::set_follow_renames(),
+            "--normalize-eol" => config::set_normalize_eol(),
+            "--check-languages" => config::set_check_languages(),
+            "--emit-sidecars" => config::set_emit_sidecars(),
+            "--encoding" => {
+                config::set_encoding(&args.next().ok_or_else(|| eyre!("--encoding needs a name"))?)?
+            }
+            "--max-lines" => config::set_max_lines(
+                args.next()
+                    .ok_or_else(|| eyre!("--max-lines needs a line count"))?
+                    .parse()?,
+            ),
+            "--tab-width" => config::set_tab_width(
+                args.next()
+                    .ok_or_else(|| eyre!("--tab-width needs a column count"))?
+                    .parse()?,
+            ),
+            "--strict" | "--fail-on-warning" => fail_on_warning = true,
+            "--jobs" => {
+                jobs = Some(
+                    args.next()
+                        .ok_or_else(|| eyre!("--jobs needs a thread count"))?
+                        .parse()?,
+                )
+            }
+            "--prefix" => {
+                prefix = args
+                    .next()
+                    .ok_or_else(|| eyre!("--prefix needs a string"))?
+            }
+            "--output-dir" => {
+                output_dir = Some(
+                    args.next()
+                        .ok_or_else(|| eyre!("--output-dir needs a path"))?
+                        .into(),
+                )
+            }
+            "--in-place" => in_place = true,
+            "--watch" => watch = true,
+            // Handled by the pre-scan above; the value still has to be consumed here
+            "--color" => {
+                args.next();
+            }
+            color_arg if color_arg.starts_with("--color=") => {}
+            "--files-from" => {
+                files_from = Some(
+                    args.next()
+                        .ok_or_else(|| eyre!("--files-from needs a path"))?
+                        .into(),
+                )
+            }
+            "--manifest-out" => {
+                manifest_out =
+                    Some(args.next().ok_or_else(|| eyre!("--manifest-out needs a path"))?.into())
+            }
+            "--combined-out" => {
+                combined_out =
+                    Some(args.next().ok_or_else(|| eyre!("--combined-out needs a path"))?.into())
+            }
+            "--quiet" => verbosity = Verbosity::Quiet,
+            "--verbose" => {
+                verbosity = Verbosity::Verbose;
+                config::set_verbose();
+            }
+            "--repo" => repo_path = Some(args.next().ok_or_else(|| eyre!("--repo needs a path"))?),
+            "--worktree" => {
+                worktree = Some(
+                    args.next()
+                        .ok_or_else(|| eyre!("--worktree needs a name or path"))?,
+                )
+            }
+            "--repo-url" => {
+                config::set_repo_url(&args.next().ok_or_else(|| eyre!("--repo-url needs a URL"))?)
+            }
+            "--copyright-pattern" => {
+                copyright_pattern = Some(
+                    args.next()
+                        .ok_or_else(|| eyre!("--copyright-pattern needs a regex"))?,
+                )
+            }
+            _ => patterns.push(arg),
+        }
+    }
+
+    // Resolve the repo path once: the flag wins, then the runtime environment, then the
+    // path that was baked in at compile time
+    let mut repo_path = repo_path
+        .or_else(|| env::var("LINTRANS_DIR").ok())
+        .unwrap_or_else(|| String::from(env!("LINTRANS_DIR")));
+
+    // A secondary worktree has its own HEAD and working tree, so snippets resolve against
+    // the experiment rather than the main checkout. Resolving it down to a path here means
+    // the per-file repo handles below need no special casing
+    if let Some(worktree) = &worktree {
+        repo_path = match Repository::open(&repo_path)?.find_worktree(worktree) {
+            Ok(found) => found.path().to_string_lossy().into_owned(),
+            Err(_) if Repository::open(worktree).is_ok() => worktree.clone(),
+            Err(_) => {
+                return Err(eyre!(
+                    "{repo_path:?} has no worktree named {worktree:?}, and it doesn't open \
+                     as a worktree path either"
+                ))
+            }
+        };
+    }
+    let repo = Repository::open(&repo_path)?;
+
+    // HEAD placeholders pin at build time, so the hash is resolved once up front and every
+    // comment sees the same concrete commit
+    if let Ok(head) = repo.head().and_then(|head| head.peel_to_commit()) {
+        comment::set_head_hash(&head.id().to_string());
+    }
+
+    // The flag is set first so that it wins over the pattern in the project config
+    if let Some(pattern) = &copyright_pattern {
+        config::set_copyright_pattern(pattern)?;
+    }
+
+    let project_config_path = Path::new(&repo_path).join(".snippets.toml");
+    if project_config_path.exists() {
+        config::load_project_config(&fs::read_to_string(project_config_path)?)?;
+    }
+
+    let manifest_path = Path::new(&repo_path).join("snippets.list");
+    if manifest_path.exists() {
+        comment::load_manifest(&fs::read_to_string(manifest_path)?)?;
+    }
+
+    // A list file sidesteps command-line length limits when a build generates hundreds of
+    // inputs; its entries are patterns like the positional arguments, one per line
+    if let Some(list_path) = &files_from {
+        for line in fs::read_to_string(list_path)?.lines() {
+            let line = line.trim();
+            if !line.is_empty() {
+                patterns.push(line.to_string());
+            }
+        }
+    }
+
+    if patterns.is_empty() {
+        return Err(eyre!("Please provide at least one file to process"));
+    }
+
+    // An empty prefix with no separate output directory would make every output path equal
+    // its input path and clobber the sources
+    if prefix.is_empty() && output_dir.is_none() && !in_place {
+        return Err(eyre!(
+            "An empty --prefix needs an --output-dir to avoid overwriting inputs"
+        ));
+    }
+
+    // With a single "-" argument, we filter stdin to stdout and touch no files
+    if patterns == ["-"] {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        let mut reports = vec![];
+        print!("{}", process_snippets(&repo, &contents, Verbosity::Quiet, &mut reports)?);
+        if let Some(manifest_path) = &manifest_out {
+            write_manifest(manifest_path, &reports)?;
+        }
+        return Ok(());
+    }
+
+    let paths = expand_patterns(&patterns, recursive)?;
+
+    if list {
+        return list_all_snippets(&paths);
+    }
+
+    if check {
+        return check_all_snippets(&repo, &paths);
+    }
+
+    if check_drift {
+        return check_all_drift(&repo, &paths);
+    }
+
+    if canonicalize {
+        return canonicalize_all_snippets(&repo, &paths, in_place);
+    }
+
+    if update_hashes {
+        return update_all_hashes(&repo, &paths);
+    }
+
+    if list_unreferenced {
+        let source_root = source_root
+            .ok_or_else(|| eyre!("--list-unreferenced needs a --source-root to audit against"))?;
+        return list_unreferenced_files(&paths, &source_root);
+    }
+
+    if watch {
+        return watch_files(
+            &repo_path,
+            &paths,
+            &prefix,
+            output_dir.as_deref(),
+            in_place,
+            jobs,
+            verbosity,
+            manifest_out.as_deref(),
+            combined_out.as_deref(),
+            summary,
+        );
+    }
+
+    run_processing_pass(
+        &repo_path,
+        &paths,
+        &prefix,
+        output_dir.as_deref(),
+        in_place,
+        jobs,
+        verbosity,
+        manifest_out.as_deref(),
+        combined_out.as_deref(),
+        summary,
+    )?;
+
+    if fail_on_warning && warnings::count() > 0 {
+        return Err(eyre!("{} warning(s) emitted", warnings::count()));
+    }
+
+    Ok(())
+}
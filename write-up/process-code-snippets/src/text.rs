@@ -0,0 +1,509 @@
+// lintrans - The linear transformation visualizer
+// Copyright (C) 2021-2022 D. Dyson (DoctorDalek1963)
+
+// This program is licensed under GNU GPLv3, available here:
+// <https://www.gnu.org/licenses/gpl-3.0.html>
+
+//! This module provides the [`Text`] struct, which holds the resolved text of a snippet and
+//! renders it as LaTeX.
+
+use git2::Oid;
+use itertools::intersperse;
+use std::path::PathBuf;
+
+use crate::config::{Backend, Config, InfoPosition};
+
+/// A single contiguous body of lines included in a snippet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Body {
+    /// The line number of the first line of this body.
+    pub first: usize,
+
+    /// The line number of the last line of this body.
+    pub last: usize,
+
+    /// The lines themselves.
+    pub lines: Vec<String>,
+}
+
+/// A struct to hold the resolved text of a snippet, ready to be rendered as LaTeX.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Text {
+    /// The hash (or symbolic ref, like a tag name) that the snippet comment pinned.
+    pub hash: String,
+
+    /// The id of the commit that `hash` resolved to.
+    pub oid: Oid,
+
+    /// The name of the file that the snippet was taken from.
+    pub filename: PathBuf,
+
+    /// The ``def`` and ``class`` lines enclosing the snippet, with their line numbers.
+    pub scopes: Vec<(usize, String)>,
+
+    /// A summary of the commits that last touched the snippet lines, shown as an extra info
+    /// comment line when the ``blame`` option is set.
+    pub blame_info: Option<String>,
+
+    /// The resolved caption text, shown below the environment with ``\captionof``.
+    pub caption: Option<String>,
+
+    /// The base URL that link-building features point at, with no trailing slash. This comes
+    /// from [`config::repo_url`](crate::config::repo_url), captured at resolution time.
+    pub repo_url: String,
+
+    /// The bodies of the snippet, one per line range.
+    pub bodies: Vec<Body>,
+
+    /// The configuration options of the snippet.
+    pub config: Config,
+}
+
+impl Text {
+    /// Render this text as a LaTeX ``minted`` environment.
+    ///
+    /// The rendered environment starts with the info comment lines (the hash and the filename,
+    /// each possibly spanning several lines with a block comment syntax) and a blank separator,
+    /// followed by the scope lines and the bodies. Line numbers are rendered with a custom
+    /// ``\theFancyVerbLine`` so that every code line shows its real line number in the file: the
+    /// ``firstnumber`` is offset to account for the info lines, the info lines themselves are
+    /// guarded to show no number, and each gap between scopes or bodies shows a ``... ``
+    /// indicator and offsets the numbers that follow it.
+    pub fn get_latex(&self) -> String {
+        let filename = self
+            .filename
+            .to_str()
+            .expect("Filename should be valid UTF-8");
+
+        // TeX-special characters are fine inside minted's verbatim context, but they can break
+        // other backends or custom lexers that interpret the info comment, so flag them
+        if filename.contains(['#', '$', '%', '&', '~', '_', '^', '\\', '{', '}']) {
+            crate::warnings::warn(&format!(
+                "filename {filename:?} contains TeX-special characters, \
+                 which may break non-verbatim backends"
+            ));
+        }
+
+        // With info_as_caption, the provenance moves into the caption and the listing body
+        // starts straight at the code, so no offset machinery is needed for header lines
+        if self.config.info_as_caption && !self.config.noinfo {
+            if self.caption.is_some() {
+                crate::warnings::warn(
+                    "info_as_caption is ignored because an explicit caption is set",
+                );
+            } else {
+                let mut text = self.clone();
+                text.config.noinfo = true;
+                text.config.info_as_caption = false;
+                text.caption = Some(format!("\\texttt{{{} {filename}}}", self.provenance()));
+                return text.get_latex();
+            }
+        }
+
+        // A trailing info position rides the provenance on the first body line as an
+        // end-of-line comment, so there are no header lines to offset out of the numbering
+        if self.config.info_position == InfoPosition::Trailing && !self.config.noinfo {
+            let mut text = self.clone();
+            text.config.noinfo = true;
+            text.config.info_position = InfoPosition::Leading;
+            if let Some(line) = text
+                .bodies
+                .first_mut()
+                .and_then(|body| body.lines.first_mut())
+            {
+                line.push_str("  ");
+                line.push_str(
+                    &self
+                        .config
+                        .info_comment_syntax
+                        .wrap(&format!("{} {filename}", self.provenance())),
+                );
+            }
+            return text.get_latex();
+        }
+
+        // With langs, each body renders as its own minted environment with the matching
+        // lexer, all still inside one group sharing the number colour
+        if let Some(langs) = &self.config.langs {
+            let languages: Vec<&str> = langs.split(',').collect();
+            if languages.len() == self.bodies.len() {
+                return self.get_multi_language_latex(&languages, filename);
+            }
+            crate::warnings::warn(&format!(
+                "langs={langs} gives {} language(s) for {} range(s); \
+                 falling back to a single environment",
+                languages.len(),
+                self.bodies.len()
+            ));
+        }
+
+        // With compact_scopes, the whole scope chain collapses onto one synthesized line,
+        // numbered as the outermost header
+        let compact_scope_line = if self.config.compact_scopes && !self.scopes.is_empty() {
+            Some(
+                self.scopes
+                    .iter()
+                    .map(|(_, line)| format!("{} ...", line.trim()))
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            )
+        } else {
+            None
+        };
+
+        // Every scope line is its own chunk, except that scopes on consecutive lines (like a
+        // decorator and its def) stay together; the bodies follow, each joining the previous
+        // chunk when it starts directly below it
+        let mut chunks: Vec<(usize, Vec<&str>)> = vec![];
+        if let Some(line) = &compact_scope_line {
+            chunks.push((self.scopes[0].0, vec![line.as_str()]));
+        } else {
+            for (number, line) in &self.scopes {
+                match chunks.last_mut() {
+                    Some((first, lines)) if *number == *first + lines.len() => {
+                        lines.push(line.as_str())
+                    }
+                    _ => chunks.push((*number, vec![line.as_str()])),
+                }
+            }
+        }
+        for body in &self.bodies {
+            let body_lines = body.lines.iter().map(String::as_str);
+            match chunks.last_mut() {
+                Some((first, lines)) if body.first == *first + lines.len() => {
+                    lines.extend(body_lines)
+                }
+                _ => chunks.push((body.first, body_lines.collect())),
+            }
+        }
+
+        // A block comment syntax can make either info comment span several lines, and noinfo
+        // drops the info lines and their blank separator entirely
+        let mut lines: Vec<String> = if self.config.noinfo {
+            vec![]
+        } else {
+            let mut lines: Vec<String> = self
+                .config
+                .info_comment_syntax
+                .wrap(&self.provenance())
+                .lines()
+                .chain(self.config.info_comment_syntax.wrap(filename).lines())
+                .map(String::from)
+                .collect();
+            if let Some(blame_info) = &self.blame_info {
+                lines.push(self.config.info_comment_syntax.wrap(blame_info));
+            }
+            // The blank separator counts towards pre_line_count via lines.len(), so dropping
+            // it here keeps the numbering aligned with no special cases below
+            if !self.config.no_separator {
+                lines.push(String::new());
+            }
+            lines
+        };
+        let pre_line_count = lines.len() as isize;
+
+        // firstnumber=N rebases the displayed numbers so the first body line shows N, with
+        // every other number (scope lines and gaps included) keeping its relative offset
+        let shift = match self.config.firstnumber {
+            Some(n) if self.config.renumber => {
+                crate::warnings::warn(&format!(
+                    "firstnumber={n} does nothing under renumber, which always starts at 1"
+                ));
+                0
+            }
+            Some(n) if !self.bodies.is_empty() => n as isize - self.bodies[0].first as isize,
+            _ => 0,
+        };
+
+        // With renumber, the displayed numbers run sequentially from 1 over the content lines
+        // instead of showing the real file line numbers
+        let first_number = if self.config.renumber {
+            1 - pre_line_count
+        } else {
+            chunks[0].0 as isize + shift - pre_line_count
+        };
+
+        // Build the verbatim lines, remembering the counter value and number offset of each
+        // gap. A gap that skips only a single line (usually a blank one) shows no ellipsis,
+        // since nothing meaningful was elided
+        let excluded: Vec<usize> = self
+            .config
+            .exclude
+            .as_deref()
+            .unwrap_or("")
+            .split(',')
+            .filter_map(|line| line.parse().ok())
+            .collect();
+        let mut counter = first_number + pre_line_count - 1;
+        let mut gaps: Vec<(isize, isize, bool)> = vec![];
+
+        for (i, (first, chunk_lines)) in chunks.iter().enumerate() {
+            if i > 0 {
+                counter += 1;
+                let gap_offset = if self.config.renumber {
+                    // Each gap line consumes a verbatim line number that the content shouldn't
+                    // count, so the offset grows by one per gap
+                    -(gaps.len() as isize + 1)
+                } else {
+                    *first as isize + shift - (counter + 1)
+                };
+                // saturating_sub covers directory snippets, whose per-file bodies restart
+                // their numbering at 0
+                let (previous_first, previous_lines) = &chunks[i - 1];
+                let skipped = first.saturating_sub(previous_first + previous_lines.len());
+                // A single skipped line usually means nothing was elided (a blank line
+                // between scopes, say), unless it was excluded on purpose
+                let show_ellipsis = skipped > 1
+                    || (previous_first + previous_lines.len()..*first)
+                        .any(|line| excluded.contains(&line));
+                gaps.push((counter, gap_offset, show_ellipsis));
+                lines.push(String::new());
+            }
+            lines.extend(chunk_lines.iter().map(|line| line.to_string()));
+            counter += chunk_lines.len() as isize;
+        }
+
+        // Build the nested \ifnum chain that renders the line numbers. The first pair of
+        // branches hides the numbers of the info lines; after that, each gap gets an
+        // equality branch showing "... " and a comparison branch offsetting the numbers
+        let mut chain = String::new();
+        let mut depth = 0;
+        let mut offset = 0;
+
+        if pre_line_count > 0 {
+            chain.push_str(&format!(
+                "\\ifnum\\value{{FancyVerbLine}}<{}\\else",
+                first_number + pre_line_count - 1
+            ));
+            depth = 1;
+        }
+
+        let ellipsis = self.config.ellipsis.as_deref().unwrap_or("... ");
+        for &(gap_counter, gap_offset, show_ellipsis) in &gaps {
+            let gap_ellipsis = if show_ellipsis { ellipsis } else { "" };
+            chain.push_str(&format!(
+                "\\ifnum\\value{{FancyVerbLine}}<{gap_counter}{}\\else\
+                 \\ifnum\\value{{FancyVerbLine}}={gap_counter}{gap_ellipsis}\\else",
+                stepped_number_expression(offset, self.config.stepnumber)
+            ));
+            depth += 2;
+            offset = gap_offset;
+        }
+
+        chain.push_str(&stepped_number_expression(offset, self.config.stepnumber));
+        chain.push_str(&"\\fi".repeat(depth));
+
+        let mut options = vec![
+            String::from("linenos"),
+            format!("firstnumber={first_number}"),
+        ];
+        if self.config.autogobble {
+            options.push(String::from("autogobble"));
+        }
+        if let Some(gobble) = self.config.gobble {
+            options.push(format!("gobble={gobble}"));
+        }
+        if self.config.breaklines {
+            options.push(String::from("breaklines"));
+        }
+        if self.config.breakanywhere {
+            options.push(String::from("breakanywhere"));
+        }
+        if let Some(highlight_lines) = &self.config.highlight_lines {
+            options.push(format!("highlightlines={{{highlight_lines}}}"));
+        }
+        if let Some(numbersep) = &self.config.numbersep {
+            options.push(format!("numbersep={numbersep}"));
+        }
+        if let Some(frame) = &self.config.frame {
+            options.push(format!("frame={frame}"));
+        }
+        if let Some(framesep) = &self.config.framesep {
+            options.push(format!("framesep={framesep}"));
+        }
+        let options = options.join(",");
+
+        let language = check_and_quote_language(self.config.language.as_deref().unwrap_or("python"));
+
+        let caption = match &self.caption {
+            Some(caption) => format!("\\captionof{{listing}}{{{caption}}}\n"),
+            None => String::new(),
+        };
+
+        let body = intersperse(lines, String::from("\n")).collect::<String>();
+        let number_color = self.number_color();
+
+        // minted's numbering is driven by fancyvrb, so the \theFancyVerbLine chain carries
+        // over unchanged; the verbatim backend just skips Pygments and the minted-only options
+        let latex = if self.config.backend == Backend::Verbatim {
+            format!(
+                "{{\\renewcommand{{\\theFancyVerbLine}}{{\\textcolor{number_color}{{{chain}}}}}\n\
+                 \\begin{{Verbatim}}[numbers=left,firstnumber={first_number}]\n\
+                 {body}\n\
+                 \\end{{Verbatim}}\n\
+                 {caption}}}"
+            )
+        } else {
+            format!(
+                "{{\\renewcommand{{\\theFancyVerbLine}}{{\\textcolor{number_color}{{{chain}}}}}\n\
+                 \\begin{{minted}}[{options}]{{{language}}}\n\
+                 {body}\n\
+                 \\end{{minted}}\n\
+                 {caption}}}"
+            )
+        };
+
+        // A listing float gets proper captioning and placement instead of sitting inline in
+        // the text flow
+        match &self.config.float {
+            Some(placement) if placement.is_empty() => {
+                format!("\\begin{{listing}}\n{latex}\n\\end{{listing}}")
+            }
+            Some(placement) => {
+                format!("\\begin{{listing}}[{placement}]\n{latex}\n\\end{{listing}}")
+            }
+            None => latex,
+        }
+    }
+
+    /// Render this text as one group of several ``minted`` environments, one per body, each
+    /// with its own language from the ``langs`` option.
+    ///
+    /// Scope lines aren't shown in this mode, since a single scope chain can't meaningfully
+    /// cover bodies in different languages. The info comment lines sit at the top of the
+    /// first environment and every later environment just restarts ``firstnumber`` at its
+    /// own first line, so the number chain needs no gap branches.
+    fn get_multi_language_latex(&self, languages: &[&str], filename: &str) -> String {
+        if !self.scopes.is_empty() {
+            crate::warnings::warn("scope lines are not shown when langs= is set");
+        }
+
+        let info_lines: Vec<String> = if self.config.noinfo {
+            vec![]
+        } else {
+            let mut lines: Vec<String> = self
+                .config
+                .info_comment_syntax
+                .wrap(&self.provenance())
+                .lines()
+                .chain(self.config.info_comment_syntax.wrap(filename).lines())
+                .map(String::from)
+                .collect();
+            if let Some(blame_info) = &self.blame_info {
+                lines.push(self.config.info_comment_syntax.wrap(blame_info));
+            }
+            if !self.config.no_separator {
+                lines.push(String::new());
+            }
+            lines
+        };
+        let pre_line_count = info_lines.len() as isize;
+
+        // Only the info lines need their numbers hidden; the bodies are numbered by their
+        // own environments, which restart the counter above the guard every time
+        let chain = if pre_line_count > 0 {
+            format!(
+                "\\ifnum\\value{{FancyVerbLine}}<{}\\else\\arabic{{FancyVerbLine}}\\fi",
+                self.bodies[0].first as isize - 1
+            )
+        } else {
+            String::from("\\arabic{FancyVerbLine}")
+        };
+
+        let mut environments: Vec<String> = vec![];
+        for (i, (body, language)) in self.bodies.iter().zip(languages).enumerate() {
+            let mut lines = if i == 0 { info_lines.clone() } else { vec![] };
+            let first_number = body.first as isize - if i == 0 { pre_line_count } else { 0 };
+            lines.extend(body.lines.iter().cloned());
+            let body_text = intersperse(lines, String::from("\n")).collect::<String>();
+            environments.push(format!(
+                "\\begin{{minted}}[linenos,firstnumber={first_number}]{{{}}}\n\
+                 {body_text}\n\
+                 \\end{{minted}}",
+                check_and_quote_language(language)
+            ));
+        }
+
+        let caption = match &self.caption {
+            Some(caption) => format!("\\captionof{{listing}}{{{caption}}}\n"),
+            None => String::new(),
+        };
+
+        format!(
+            "{{\\renewcommand{{\\theFancyVerbLine}}{{\\textcolor{}{{{chain}}}}}\n\
+             {}\n\
+             {caption}}}",
+            self.number_color(),
+            environments.join("\n")
+        )
+    }
+
+    /// Return the provenance shown in the info comment: the pinned hash as-is, or a symbolic
+    /// ref like a tag with the resolved commit alongside it, so the listing stays both
+    /// human-meaningful and reproducible.
+    fn provenance(&self) -> String {
+        if self.hash.chars().all(|c| c.is_ascii_hexdigit())
+            || self.hash == "WORKTREE"
+            || self.oid.is_zero()
+        {
+            self.hash.clone()
+        } else {
+            format!("{} ({})", self.hash, &self.oid.to_string()[..8])
+        }
+    }
+
+    /// Return the ``\textcolor`` argument for the line number gutter, which takes either an
+    /// rgb triple or a named color and defaults to the light blue the write-up has always
+    /// used.
+    fn number_color(&self) -> String {
+        match &self.config.linenum_color {
+            Some(color) if color.contains(',') => format!("[rgb]{{{color}}}"),
+            Some(color) => format!("{{{color}}}"),
+            None => String::from("[rgb]{0.5,0.5,1}"),
+        }
+    }
+}
+
+/// Warn if the language isn't a known Pygments lexer alias, and quote it for minted if it
+/// contains whitespace, as a custom lexer invocation like "lexers.py:MyLexer -x" does.
+fn check_and_quote_language(language: &str) -> String {
+    if crate::config::check_languages() && !crate::languages::is_known(language) {
+        crate::warnings::warn(&format!(
+            "{language:?} is not a known Pygments lexer alias"
+        ));
+    }
+    if language.contains(char::is_whitespace) {
+        format!("'{language}'")
+    } else {
+        language.to_string()
+    }
+}
+
+/// Return the TeX expression that renders the current line number with the given offset,
+/// showing it only when the displayed number is a multiple of `step` (if one is set).
+///
+/// fancyvrb has its own ``stepnumber``, but its divisibility test runs on the raw counter,
+/// which goes wrong as soon as a gap offsets the displayed numbers; building the test into
+/// the macro keeps it aligned with what the reader sees, and leaves the ``... `` gap
+/// indicators untouched.
+fn stepped_number_expression(offset: isize, step: Option<usize>) -> String {
+    let expression = number_expression(offset);
+    let Some(step) = step.filter(|&step| step > 1) else {
+        return expression;
+    };
+    let displayed = if offset == 0 {
+        String::from("\\value{FancyVerbLine}")
+    } else {
+        format!("(\\value{{FancyVerbLine}}{offset:+})")
+    };
+    format!("\\ifnum\\numexpr{displayed}-{displayed}/{step}*{step}\\relax=0 {expression}\\fi")
+}
+
+/// Return the TeX expression that renders the current line number with the given offset.
+fn number_expression(offset: isize) -> String {
+    if offset == 0 {
+        String::from("\\arabic{FancyVerbLine}")
+    } else {
+        format!("\\the\\numexpr\\value{{FancyVerbLine}}{offset:+}\\relax")
+    }
+}
@@ -0,0 +1,24 @@
+// lintrans - The linear transformation visualizer
+// Copyright (C) 2021-2022 D. Dyson (DoctorDalek1963)
+
+// This program is licensed under GNU GPLv3, available here:
+// <https://www.gnu.org/licenses/gpl-3.0.html>
+
+//! This module provides a tiny global warning collector, so that warnings can be printed as they
+//! happen and still be counted at the end of the run for ``--fail-on-warning``.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// The number of warnings emitted so far.
+static WARNING_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Emit a warning: print it to stderr and remember that it happened.
+pub fn warn(message: &str) {
+    eprintln!("Warning: {message}");
+    WARNING_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Return the number of warnings emitted so far.
+pub fn count() -> u32 {
+    WARNING_COUNT.load(Ordering::Relaxed)
+}
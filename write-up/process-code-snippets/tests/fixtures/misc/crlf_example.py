@@ -0,0 +1,4 @@
+# A CRLF-terminated file, used by the line-ending tests
+
+GREETING = "hello"
+FAREWELL = "goodbye"
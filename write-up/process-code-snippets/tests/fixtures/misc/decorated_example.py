@@ -0,0 +1,18 @@
+"""A decorated class with a wrapped signature, used by the scope-detection tests."""
+
+
+class Widget:
+    """A stand-in for a Qt widget."""
+
+    @property
+    def size(self):
+        return 42
+
+    @staticmethod
+    @functools.cache
+    def expensive(
+        first,
+        second,
+    ):
+        value = first + second
+        return value
@@ -0,0 +1,3 @@
+# Un exemple encod� en Latin-1
+
+ACCENT = "caf�"
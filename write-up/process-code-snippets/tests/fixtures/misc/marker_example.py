@@ -0,0 +1,13 @@
+"""A small file with snippet markers, used by the marker tests."""
+
+
+# SNIPPET-START parser
+def parse():
+    return 42
+# SNIPPET-END parser
+
+
+# SNIPPET-START empty
+# SNIPPET-END empty
+
+# SNIPPET-START unbalanced
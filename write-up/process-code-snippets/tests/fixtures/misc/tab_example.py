@@ -0,0 +1,7 @@
+"""A file that mixes tabs and spaces, used by the indentation tests."""
+
+
+class Grid:
+	def draw(self):
+	    total = 0
+	    return total